@@ -27,12 +27,6 @@ impl Component for UartConsoleComponent {
         mk66::uart::UART0.set_client(console);
         console.initialize();
 
-        let kc = static_init!(
-                capsules::console::App,
-                capsules::console::App::default()
-            );
-        kernel::debug::assign_console_driver(Some(console), kc);
-
         Some(console)
     }
 }
@@ -0,0 +1,34 @@
+use mk66;
+use kernel;
+use ewm;
+use components::Component;
+
+/// The External Watchdog Monitor, serviced by a supervisor app. To drive
+/// EWM_OUT on a pin, see `pins::configure_ewm_pins`.
+pub struct EwmComponent {
+    config: mk66::ewm::Config,
+}
+
+impl EwmComponent {
+    pub fn new(config: mk66::ewm::Config) -> Self {
+        EwmComponent {
+            config: config,
+        }
+    }
+}
+
+impl Component for EwmComponent {
+    type Output = &'static ewm::EwmSupervisor<'static>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        let supervisor = static_init!(
+                ewm::EwmSupervisor<'static>,
+                ewm::EwmSupervisor::new(&mk66::ewm::EWM,
+                                        self.config,
+                                        kernel::Grant::create())
+            );
+        mk66::ewm::EWM.set_client(supervisor);
+
+        Some(supervisor)
+    }
+}
@@ -0,0 +1,71 @@
+use mk66;
+use kernel;
+use halfduplex;
+use kernel::hil::uart::UART;
+use mk66::uart::TransmitEnablePolarity;
+use components::{Component, ComponentWithDependency};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+type AlarmMux = &'static MuxAlarm<'static, mk66::pit::Pit<'static>>;
+
+/// RS-485 half-duplex serial on UART1, with the transceiver's driver enable
+/// on UART1_RTS. See `pins::configure_rs485_pins`.
+pub struct HalfDuplexComponent {
+    baud_rate: u32,
+    polarity: TransmitEnablePolarity,
+    echo: bool,
+    mux: Option<AlarmMux>
+}
+
+impl HalfDuplexComponent {
+    pub fn new(baud_rate: u32, polarity: TransmitEnablePolarity, echo: bool) -> Self {
+        HalfDuplexComponent {
+            baud_rate: baud_rate,
+            polarity: polarity,
+            echo: echo,
+            mux: None
+        }
+    }
+}
+
+impl Component for HalfDuplexComponent {
+    type Output = &'static halfduplex::HalfDuplexSerial<'static,
+                                                        VirtualMuxAlarm<'static, mk66::pit::Pit<'static>>>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        if self.mux.is_none() {
+            return None;
+        }
+
+        let virtual_alarm = static_init!(
+                VirtualMuxAlarm<'static, mk66::pit::Pit>,
+                VirtualMuxAlarm::new(self.mux.unwrap())
+            );
+        let serial = static_init!(
+                halfduplex::HalfDuplexSerial<'static, VirtualMuxAlarm<'static, mk66::pit::Pit>>,
+                halfduplex::HalfDuplexSerial::new(&mk66::uart::UART1,
+                                                  virtual_alarm,
+                                                  self.baud_rate,
+                                                  self.polarity,
+                                                  self.echo,
+                                                  &mut halfduplex::WRITE_BUF,
+                                                  &mut halfduplex::READ_BUF,
+                                                  kernel::Grant::create())
+            );
+        virtual_alarm.set_client(serial);
+        mk66::uart::UART1.set_client(serial);
+        if serial.initialize(&mut halfduplex::RING_BUF) != kernel::ReturnCode::SUCCESS {
+            return None;
+        }
+
+        Some(serial)
+    }
+}
+
+impl ComponentWithDependency<AlarmMux> for HalfDuplexComponent {
+    fn dependency(&mut self, mux: AlarmMux) -> &mut Self {
+        self.mux = Some(mux);
+
+        self
+    }
+}
@@ -15,11 +15,25 @@ mod alarm;
 mod console;
 mod xconsole;
 mod rnga;
+mod smartcard;
+mod halfduplex;
+mod xmodem;
+mod process_console;
+mod supervisor;
+mod reset;
+mod ewm;
 
 pub use self::gpio::GpioComponent;
 pub use self::led::LedComponent;
 pub use self::spi::VirtualSpiComponent;
-pub use self::alarm::AlarmComponent;
+pub use self::alarm::{AlarmMuxComponent, AlarmComponent};
 pub use self::console::UartConsoleComponent;
 pub use self::xconsole::XConsoleComponent;
 pub use self::rnga::RngaComponent;
+pub use self::smartcard::SmartCardComponent;
+pub use self::halfduplex::HalfDuplexComponent;
+pub use self::xmodem::XModemComponent;
+pub use self::process_console::ProcessConsoleComponent;
+pub use self::supervisor::SupervisorComponent;
+pub use self::reset::ResetReasonComponent;
+pub use self::ewm::EwmComponent;
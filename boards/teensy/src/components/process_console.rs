@@ -0,0 +1,58 @@
+use mk66;
+use process_console;
+use supervisor::ProcessControl;
+use kernel::hil::uart::UART;
+use components::{Component, ComponentWithDependency};
+
+/// Process console on a UART other than the XConsole one. The board must
+/// also mux the UART's pins, e.g. with `pins::configure_uart2_pins`.
+pub struct ProcessConsoleComponent {
+    uart: &'static mk66::uart::Uart,
+    baud_rate: u32,
+    processes: Option<&'static ProcessControl>
+}
+
+impl ProcessConsoleComponent {
+    pub fn new(uart: &'static mk66::uart::Uart, baud_rate: u32) -> Self {
+        ProcessConsoleComponent {
+            uart: uart,
+            baud_rate: baud_rate,
+            processes: None
+        }
+    }
+}
+
+impl Component for ProcessConsoleComponent {
+    type Output = &'static process_console::ProcessConsole<'static, mk66::uart::Uart>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        let processes = match self.processes {
+            Some(processes) => processes,
+            None => return None,
+        };
+
+        let console = static_init!(
+                process_console::ProcessConsole<'static, mk66::uart::Uart>,
+                process_console::ProcessConsole::new(self.uart,
+                                                     self.baud_rate,
+                                                     processes,
+                                                     &mut process_console::WRITE_BUF,
+                                                     &mut process_console::PENDING_BUF,
+                                                     &mut process_console::READ_BUF,
+                                                     &mut process_console::LINE_BUF)
+            );
+        self.uart.set_client(console);
+        console.initialize();
+
+        Some(console)
+    }
+}
+
+/// The supervisor, through which processes are stopped and started.
+impl ComponentWithDependency<&'static ProcessControl> for ProcessConsoleComponent {
+    fn dependency(&mut self, processes: &'static ProcessControl) -> &mut Self {
+        self.processes = Some(processes);
+
+        self
+    }
+}
@@ -0,0 +1,24 @@
+use mk66;
+use reset::ResetReason;
+use components::Component;
+
+pub struct ResetReasonComponent;
+
+impl ResetReasonComponent {
+    pub fn new() -> Self {
+        ResetReasonComponent {}
+    }
+}
+
+impl Component for ResetReasonComponent {
+    type Output = &'static ResetReason;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        let reset = static_init!(
+                ResetReason,
+                ResetReason::new(mk66::rcm::reset_status())
+        );
+
+        Some(reset)
+    }
+}
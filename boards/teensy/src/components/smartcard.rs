@@ -0,0 +1,70 @@
+use mk66;
+use kernel;
+use smartcard;
+use kernel::hil::uart::UART;
+use components::{Component, ComponentWithDependency};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+type AlarmMux = &'static MuxAlarm<'static, mk66::pit::Pit<'static>>;
+type PinHandle = &'static mk66::gpio::Gpio<'static>;
+
+/// Smartcard interface on UART0. UART0 is otherwise the XConsole, so a
+/// board with a smartcard moves the console to another UART.
+pub struct SmartCardComponent {
+    card_clock_hz: u32,
+    mux: Option<AlarmMux>,
+    reset_pin: Option<PinHandle>
+}
+
+impl SmartCardComponent {
+    pub fn new(card_clock_hz: u32) -> Self {
+        SmartCardComponent {
+            card_clock_hz: card_clock_hz,
+            mux: None,
+            reset_pin: None
+        }
+    }
+}
+
+impl Component for SmartCardComponent {
+    type Output = &'static smartcard::SmartCard<'static,
+                                                VirtualMuxAlarm<'static, mk66::pit::Pit<'static>>>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        if self.mux.is_none() || self.reset_pin.is_none() {
+            return None;
+        }
+
+        let virtual_alarm = static_init!(
+                VirtualMuxAlarm<'static, mk66::pit::Pit>,
+                VirtualMuxAlarm::new(self.mux.unwrap())
+            );
+        let smartcard = static_init!(
+                smartcard::SmartCard<'static, VirtualMuxAlarm<'static, mk66::pit::Pit>>,
+                smartcard::SmartCard::new(&mk66::uart::UART0,
+                                          self.reset_pin.unwrap(),
+                                          virtual_alarm,
+                                          self.card_clock_hz,
+                                          &mut smartcard::TX_BUF,
+                                          &mut smartcard::RX_BUF,
+                                          &mut smartcard::APDU_BUF,
+                                          &mut smartcard::RESPONSE_BUF,
+                                          kernel::Grant::create())
+            );
+        virtual_alarm.set_client(smartcard);
+        mk66::uart::UART0.set_client(smartcard);
+        mk66::uart::UART0.set_iso7816_client(smartcard);
+        smartcard.initialize(&mut smartcard::RING_BUF);
+
+        Some(smartcard)
+    }
+}
+
+impl ComponentWithDependency<(AlarmMux, PinHandle)> for SmartCardComponent {
+    fn dependency(&mut self, deps: (AlarmMux, PinHandle)) -> &mut Self {
+        self.mux = Some(deps.0);
+        self.reset_pin = Some(deps.1);
+
+        self
+    }
+}
@@ -39,7 +39,7 @@ impl Component for VirtualSpiComponent {
 
         let virtual_spi = static_init!(
                 [VirtualSpiMasterDevice<'static, mk66::spi::Spi<'static>>; 3],
-                [VirtualSpiMasterDevice::new(mux_spi0, 0),
+                [VirtualSpiMasterDevice::new(mux_spi0, 4),
                  VirtualSpiMasterDevice::new(mux_spi1, 0),
                  VirtualSpiMasterDevice::new(mux_spi2, 0)]
             );
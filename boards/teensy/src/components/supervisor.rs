@@ -0,0 +1,62 @@
+use mk66;
+use supervisor::{self, LoadedApp};
+use kernel::procs::{Process, FaultResponse};
+use components::{Component, ComponentWithDependency};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+type AlarmMux = &'static MuxAlarm<'static, mk66::pit::Pit<'static>>;
+type StoppedTable = &'static mut [Option<&'static mut Process<'static>>];
+type AppTable = &'static mut [LoadedApp];
+
+pub struct SupervisorComponent {
+    fault_response: FaultResponse,
+    deps: Option<(AlarmMux, StoppedTable, AppTable)>
+}
+
+impl SupervisorComponent {
+    pub fn new(fault_response: FaultResponse) -> Self {
+        SupervisorComponent {
+            fault_response: fault_response,
+            deps: None
+        }
+    }
+}
+
+impl Component for SupervisorComponent {
+    type Output = &'static supervisor::ProcessSupervisor<'static,
+                                                         VirtualMuxAlarm<'static, mk66::pit::Pit<'static>>>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        let (mux, stopped, apps) = match self.deps.take() {
+            Some(deps) => deps,
+            None => return None,
+        };
+
+        let virtual_alarm = static_init!(
+                VirtualMuxAlarm<'static, mk66::pit::Pit>,
+                VirtualMuxAlarm::new(mux)
+            );
+        let supervisor = static_init!(
+                supervisor::ProcessSupervisor<'static, VirtualMuxAlarm<'static, mk66::pit::Pit>>,
+                supervisor::ProcessSupervisor::new(virtual_alarm,
+                                                   stopped,
+                                                   apps,
+                                                   self.fault_response)
+            );
+        virtual_alarm.set_client(supervisor);
+        mk66::chip::set_process_fault_client(supervisor);
+        supervisor.start();
+
+        Some(supervisor)
+    }
+}
+
+/// The alarm mux, a table to hold stopped processes, and the apps loaded
+/// into the process table, which is the same length.
+impl ComponentWithDependency<(AlarmMux, StoppedTable, AppTable)> for SupervisorComponent {
+    fn dependency(&mut self, deps: (AlarmMux, StoppedTable, AppTable)) -> &mut Self {
+        self.deps = Some(deps);
+
+        self
+    }
+}
@@ -1,14 +1,19 @@
 use mk66;
 use kernel;
+use io;
 use xconsole;
 use kernel::hil::uart::UART;
 use components::Component;
 
-pub struct XConsoleComponent;
+pub struct XConsoleComponent {
+    uart: &'static mk66::uart::Uart,
+}
 
 impl XConsoleComponent {
-    pub fn new() -> Self {
-        XConsoleComponent {}
+    pub fn new(uart: &'static mk66::uart::Uart) -> Self {
+        XConsoleComponent {
+            uart: uart,
+        }
     }
 }
 
@@ -18,23 +23,22 @@ impl Component for XConsoleComponent {
     unsafe fn finalize(&mut self) -> Option<Self::Output> {
         let xconsole = static_init!(
                 xconsole::XConsole<mk66::uart::Uart>,
-                xconsole::XConsole::new(&mk66::uart::UART0,
+                xconsole::XConsole::new(self.uart,
                                         115200,
                                         &mut xconsole::WRITE_BUF,
                                         &mut xconsole::READ_BUF,
+                                        &mut xconsole::ECHO_BUF,
                                         kernel::Grant::create())
             );
-        mk66::uart::UART0.set_client(xconsole);
+        self.uart.set_client(xconsole);
         xconsole.initialize();
 
-        let kc = static_init!(
-                xconsole::App,
-                xconsole::App::default()
-            );
-        kernel::debug::assign_console_driver(Some(xconsole), kc);
+        // Debug output goes through the board's `debug!` and `io::DEBUG_BUFFER`,
+        // so the kernel's own debug writer is left without a console.
+        io::set_debug_console(xconsole);
 
-        mk66::uart::UART0.enable_rx();
-        mk66::uart::UART0.enable_rx_interrupts();
+        self.uart.enable_rx();
+        self.uart.enable_rx_interrupts();
 
         Some(xconsole)
     }
@@ -0,0 +1,61 @@
+use mk66;
+use kernel;
+use xconsole;
+use xmodem;
+use components::{Component, ComponentWithDependency};
+use capsules::virtual_alarm::{MuxAlarm, VirtualMuxAlarm};
+
+type Console = &'static xconsole::XConsole<'static, mk66::uart::Uart>;
+type AlarmMux = &'static MuxAlarm<'static, mk66::pit::Pit<'static>>;
+
+/// XMODEM/YMODEM receiver on the XConsole UART.
+pub struct XModemComponent {
+    console: Option<Console>,
+    mux: Option<AlarmMux>
+}
+
+impl XModemComponent {
+    pub fn new() -> Self {
+        XModemComponent {
+            console: None,
+            mux: None
+        }
+    }
+}
+
+impl Component for XModemComponent {
+    type Output = &'static xmodem::XModem<'static,
+                                          mk66::uart::Uart,
+                                          VirtualMuxAlarm<'static, mk66::pit::Pit<'static>>>;
+
+    unsafe fn finalize(&mut self) -> Option<Self::Output> {
+        if self.console.is_none() || self.mux.is_none() {
+            return None;
+        }
+
+        let virtual_alarm = static_init!(
+                VirtualMuxAlarm<'static, mk66::pit::Pit>,
+                VirtualMuxAlarm::new(self.mux.unwrap())
+            );
+        let xmodem = static_init!(
+                xmodem::XModem<'static, mk66::uart::Uart, VirtualMuxAlarm<'static, mk66::pit::Pit>>,
+                xmodem::XModem::new(self.console.unwrap(),
+                                    virtual_alarm,
+                                    &mut xmodem::BLOCK_BUF,
+                                    kernel::Grant::create())
+            );
+        virtual_alarm.set_client(xmodem);
+        self.console.unwrap().set_input_client(xmodem);
+
+        Some(xmodem)
+    }
+}
+
+impl ComponentWithDependency<(Console, AlarmMux)> for XModemComponent {
+    fn dependency(&mut self, deps: (Console, AlarmMux)) -> &mut Self {
+        self.console = Some(deps.0);
+        self.mux = Some(deps.1);
+
+        self
+    }
+}
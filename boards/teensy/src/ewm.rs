@@ -0,0 +1,120 @@
+//! Makes one supervisor app responsible for servicing the External
+//! Watchdog Monitor.
+//!
+//! The first app to claim the EWM starts it and becomes the only app that
+//! may service it. From then on the supervisor must service it inside the
+//! configured window, or EWM_OUT resets the external circuitry. The EWM
+//! cannot be stopped, so if the supervisor dies and is not restarted, the
+//! external circuitry is reset. A restarted supervisor keeps its claim.
+//!
+//! Usage
+//! -----
+//!
+//! ```c
+//! subscribe(EWM_DRIVER_NUM, 0, callback);  // EWM_OUT asserted
+//! command(EWM_DRIVER_NUM, 1, 0, 0);        // claim and start
+//! command(EWM_DRIVER_NUM, 2, 0, 0);        // service, once per window
+//! ```
+
+use core::cell::Cell;
+use kernel::{AppId, Grant, Callback, Driver, ReturnCode};
+use mk66::ewm::{self, Ewm, Config};
+
+pub const DRIVER_NUM: usize = 0x00090005;
+
+pub struct App {
+    callback: Option<Callback>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+        }
+    }
+}
+
+pub struct EwmSupervisor<'a> {
+    ewm: &'a Ewm<'a>,
+    config: Config,
+    apps: Grant<App>,
+    supervisor: Cell<Option<AppId>>,
+}
+
+impl<'a> EwmSupervisor<'a> {
+    pub fn new(ewm: &'a Ewm<'a>, config: Config, container: Grant<App>) -> EwmSupervisor<'a> {
+        EwmSupervisor {
+            ewm: ewm,
+            config: config,
+            apps: container,
+            supervisor: Cell::new(None),
+        }
+    }
+
+    fn claim(&self, appid: AppId) -> ReturnCode {
+        match self.supervisor.get() {
+            Some(supervisor) if supervisor != appid => ReturnCode::EBUSY,
+            Some(_) => ReturnCode::SUCCESS,
+            None => {
+                if !self.ewm.start(self.config) {
+                    return ReturnCode::FAIL;
+                }
+                self.supervisor.set(Some(appid));
+                ReturnCode::SUCCESS
+            }
+        }
+    }
+}
+
+impl<'a> Driver for EwmSupervisor<'a> {
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: EWM_OUT was asserted
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.apps.enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    /// EWM operations
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Claim the EWM and start it.
+    /// - `2`: Service the EWM. Only the app that claimed it may do this.
+    fn command(&self, cmd_num: usize, _: usize, _: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* claim */ => self.claim(appid),
+            2 /* service */ => {
+                if self.supervisor.get() != Some(appid) {
+                    return ReturnCode::ERESERVE;
+                }
+                self.ewm.service();
+                ReturnCode::SUCCESS
+            }
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+impl<'a> ewm::Client for EwmSupervisor<'a> {
+    fn asserted(&self) {
+        self.supervisor.get().map(|supervisor| {
+            let _ = self.apps.enter(supervisor, |app, _| {
+                app.callback.map(|mut cb| {
+                    cb.schedule(0, 0, 0);
+                });
+            });
+        });
+    }
+}
@@ -0,0 +1,325 @@
+//! Provides userspace with request/response access to a half-duplex RS-485
+//! bus.
+//!
+//! The UART runs in RS-485 mode, so the transceiver's driver enable follows
+//! RTS in hardware: it is asserted one bit time before the first start bit
+//! and released right after the last stop bit. This capsule handles the rest
+//! of the turnaround. Before each request it leaves the bus quiet for a
+//! configurable number of bit times so the previous responder has released
+//! it. Bytes that arrive before the reply receive is armed are kept by the
+//! UART ring buffer, so the start of a fast reply is not lost. Replies end
+//! when the line goes idle or the read buffer fills.
+//!
+//! Transceivers whose receiver stays on while they drive the bus echo each
+//! request back to the UART. For those, exactly the request's length is
+//! dropped from the front of the ring once it has been sent, which keeps
+//! any reply bytes that arrived behind the echo.
+//!
+//! Usage
+//! -----
+//!
+//! ```c
+//! subscribe(HALFDUPLEX_DRIVER_NUM, 0, callback);  // (status, reply_len, 0)
+//! allow(HALFDUPLEX_DRIVER_NUM, 0, reply, sizeof(reply));
+//! allow(HALFDUPLEX_DRIVER_NUM, 1, request, request_len);
+//! command(HALFDUPLEX_DRIVER_NUM, 1, request_len, 0);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Grant, Callback, Shared, Driver, ReturnCode};
+use kernel::common::cells::TakeCell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::uart::{self, UART, Client};
+use mk66::uart::{Uart, UartExtended, UartOptions, TransmitEnablePolarity};
+
+pub const DRIVER_NUM: usize = 0x00090002;
+
+pub static mut WRITE_BUF: [u8; 256] = [0; 256];
+pub static mut READ_BUF: [u8; 256] = [0; 256];
+/// Room for the echo of a whole request, and the start of the reply.
+pub static mut RING_BUF: [u8; 320] = [0; 320];
+
+/// Default bus quiet time before a request, in bit times. Modbus RTU
+/// requires 3.5 character times between frames.
+const DEFAULT_TURNAROUND_BITS: usize = 40;
+
+/// Longest turnaround an app may ask for, in bit times.
+const MAX_TURNAROUND_BITS: usize = 400;
+
+pub struct App {
+    callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            read_buffer: None,
+            write_buffer: None,
+        }
+    }
+}
+
+pub struct HalfDuplexSerial<'a, A: Alarm + 'a> {
+    uart: &'a Uart,
+    alarm: &'a A,
+    baud_rate: u32,
+    polarity: TransmitEnablePolarity,
+    echo: bool,
+    apps: Grant<App>,
+    current_app: Cell<Option<AppId>>,
+    expect_reply: Cell<bool>,
+    /// Length of the request being sent
+    sent: Cell<usize>,
+    turnaround_bits: Cell<usize>,
+    /// Length of the request waiting for the turnaround time to pass
+    waiting: Cell<Option<usize>>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+}
+
+impl<'a, A: Alarm> HalfDuplexSerial<'a, A> {
+    /// `echo` says whether the transceiver's receiver stays on while it
+    /// drives the bus, so that the UART hears each request.
+    pub fn new(uart: &'a Uart,
+               alarm: &'a A,
+               baud_rate: u32,
+               polarity: TransmitEnablePolarity,
+               echo: bool,
+               tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
+               container: Grant<App>)
+               -> HalfDuplexSerial<'a, A> {
+        HalfDuplexSerial {
+            uart: uart,
+            alarm: alarm,
+            baud_rate: baud_rate,
+            polarity: polarity,
+            echo: echo,
+            apps: container,
+            current_app: Cell::new(None),
+            expect_reply: Cell::new(false),
+            sent: Cell::new(0),
+            turnaround_bits: Cell::new(DEFAULT_TURNAROUND_BITS),
+            waiting: Cell::new(None),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+        }
+    }
+
+    pub fn initialize(&self, ring_buffer: &'static mut [u8]) -> ReturnCode {
+        let mut options = UartOptions::default();
+        options.rs485 = Some(self.polarity);
+
+        let params = uart::UARTParams {
+            baud_rate: self.baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        };
+        if let Err(error) = self.uart.configure(params, options) {
+            return error;
+        }
+        self.uart.enable_rx_ring(ring_buffer);
+        ReturnCode::SUCCESS
+    }
+
+    /// Keeps the bus quiet for the turnaround time, then sends the `len`
+    /// byte request.
+    fn turnaround(&self, len: usize) {
+        let bits = self.turnaround_bits.get() as u64;
+        let ticks = bits * A::Frequency::frequency() as u64 / self.baud_rate as u64;
+        if ticks == 0 {
+            self.transmit(len);
+            return;
+        }
+
+        self.waiting.set(Some(len));
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks as u32));
+    }
+
+    fn transmit(&self, len: usize) {
+        // Anything buffered so far is stale traffic, since the reply can't
+        // start before the request is sent.
+        self.uart.clear_rx_ring();
+        self.sent.set(len);
+        self.tx_buffer.take().map(|buffer| {
+            self.uart.transmit(buffer, len);
+        });
+    }
+
+    fn send(&self, appid: AppId, len: usize, expect_reply: bool) -> ReturnCode {
+        if self.current_app.get().is_some() {
+            return ReturnCode::EBUSY;
+        }
+
+        let result = self.apps.enter(appid, |app, _| {
+            app.write_buffer.as_ref().map_or(ReturnCode::ERESERVE, |src| {
+                self.tx_buffer.map_or(ReturnCode::EBUSY, |buffer| {
+                    if len > src.len() || len > buffer.len() {
+                        return ReturnCode::ESIZE;
+                    }
+                    buffer[..len].copy_from_slice(&src.as_ref()[..len]);
+                    ReturnCode::SUCCESS
+                })
+            })
+        }).unwrap_or_else(|err| err.into());
+        if result != ReturnCode::SUCCESS {
+            return result;
+        }
+
+        self.current_app.set(Some(appid));
+        self.expect_reply.set(expect_reply);
+        self.turnaround(len);
+        ReturnCode::SUCCESS
+    }
+
+    fn reply_len(&self, appid: AppId) -> usize {
+        let app_len = self.apps.enter(appid, |app, _| {
+            app.read_buffer.as_ref().map_or(0, |buffer| buffer.len())
+        }).unwrap_or(0);
+        self.rx_buffer.map_or(0, |buffer| cmp::min(app_len, buffer.len()))
+    }
+
+    fn done(&self, appid: AppId, result: ReturnCode, len: usize) {
+        self.current_app.set(None);
+        let _ = self.apps.enter(appid, |app, _| {
+            app.callback.map(|mut cb| {
+                cb.schedule(isize::from(result) as usize, len, 0);
+            });
+        });
+    }
+}
+
+impl<'a, A: Alarm> time::Client for HalfDuplexSerial<'a, A> {
+    fn fired(&self) {
+        self.waiting.take().map(|len| self.transmit(len));
+    }
+}
+
+impl<'a, A: Alarm> Driver for HalfDuplexSerial<'a, A> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Writeable buffer for replies
+    /// - `1`: Buffer holding the request
+    fn allow(&self, appid: AppId, allow_num: usize, slice: Option<AppSlice<Shared, u8>>) -> ReturnCode {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.read_buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.write_buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Request (and reply) complete
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.apps.enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    /// Bus operations
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Send the `arg1` byte request and receive the reply.
+    /// - `2`: Send the `arg1` byte request without waiting for a reply.
+    /// - `3`: Stop waiting for a reply, returning what has been received.
+    /// - `4`: Set the turnaround time to `arg1` bit times, at most 400.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* transaction */ => self.send(appid, arg1, true),
+            2 /* send only */ => self.send(appid, arg1, false),
+            3 /* cancel */ => {
+                if self.current_app.get() != Some(appid) {
+                    return ReturnCode::EINVAL;
+                }
+                if self.waiting.take().is_some() {
+                    // Nothing has been sent yet.
+                    self.alarm.disable();
+                    self.done(appid, ReturnCode::SUCCESS, 0);
+                    return ReturnCode::SUCCESS;
+                }
+                self.uart.abort_receive();
+                ReturnCode::SUCCESS
+            },
+            4 /* turnaround */ => {
+                if arg1 > MAX_TURNAROUND_BITS {
+                    return ReturnCode::EINVAL;
+                }
+                self.turnaround_bits.set(arg1);
+                ReturnCode::SUCCESS
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+impl<'a, A: Alarm> Client for HalfDuplexSerial<'a, A> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+
+        self.current_app.get().map(|appid| {
+            if !self.expect_reply.get() {
+                self.done(appid, ReturnCode::SUCCESS, 0);
+                return;
+            }
+
+            if self.echo {
+                self.uart.discard_rx(self.sent.get());
+            }
+            let len = self.reply_len(appid);
+            if len == 0 {
+                self.done(appid, ReturnCode::ERESERVE, 0);
+                return;
+            }
+            self.rx_buffer.take().map(|buffer| {
+                self.uart.receive_until_idle(buffer, len);
+            });
+        });
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, _error: uart::Error) {
+        self.current_app.get().map(|appid| {
+            let mut copied = 0;
+            let _ = self.apps.enter(appid, |app, _| {
+                app.read_buffer.as_mut().map(|dest| {
+                    copied = cmp::min(rx_len, dest.len());
+                    dest.as_mut()[..copied].copy_from_slice(&rx_buffer[..copied]);
+                });
+            });
+            self.done(appid, ReturnCode::SUCCESS, copied);
+        });
+        self.rx_buffer.replace(rx_buffer);
+    }
+}
@@ -3,16 +3,27 @@ use kernel::hil::uart::{self, UART};
 use kernel::hil::led;
 use kernel::debug;
 use mk66::{self, gpio};
+use xconsole::{XConsole, KernelOutput};
+
+/// Synchronous output on the console UART, used at panic time once the
+/// system can no longer be trusted to service interrupts.
 
 pub struct Writer {
     initialized: bool,
+    uart: Option<&'static mk66::uart::Uart>,
 }
 
-pub static mut WRITER: Writer = Writer { initialized: false };
+pub static mut WRITER: Writer = Writer { initialized: false, uart: None };
+
+/// Sends synchronous output to `uart` instead of UART0. Must be called
+/// before anything is written.
+pub unsafe fn set_console_uart(uart: &'static mk66::uart::Uart) {
+    WRITER.uart = Some(uart);
+}
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
-        let uart = unsafe { &mut mk66::uart::UART0 };
+        let uart = self.uart.unwrap_or(unsafe { &mk66::uart::UART0 });
         if !self.initialized {
             self.initialized = true;
             uart.init(uart::UARTParams {
@@ -33,11 +44,125 @@ impl Write for Writer {
     }
 }
 
+const DEBUG_BUFFER_LEN: usize = 1024;
+
+/// Queue for `print!`, `println!` and `debug!`. Formatting into it never
+/// blocks; the console drains it from the UART interrupt. When it is full,
+/// output is dropped and counted, and the count is reported inline once
+/// there is room again.
+pub struct DebugBuffer {
+    buffer: [u8; DEBUG_BUFFER_LEN],
+    head: usize,
+    len: usize,
+    dropped: usize,
+    unreported: usize,
+    count: usize,
+}
+
+pub static mut DEBUG_BUFFER: DebugBuffer = DebugBuffer {
+    buffer: [0; DEBUG_BUFFER_LEN],
+    head: 0,
+    len: 0,
+    dropped: 0,
+    unreported: 0,
+    count: 0,
+};
+
+static mut DEBUG_CONSOLE: Option<&'static XConsole<'static, mk66::uart::Uart>> = None;
+
+impl DebugBuffer {
+    fn push(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            if self.len == DEBUG_BUFFER_LEN {
+                self.dropped += 1;
+                self.unreported += 1;
+            } else {
+                self.buffer[(self.head + self.len) % DEBUG_BUFFER_LEN] = *byte;
+                self.len += 1;
+            }
+        }
+    }
+
+    /// Moves up to `buffer.len()` queued bytes into `buffer`, oldest first.
+    fn pop(&mut self, buffer: &mut [u8]) -> usize {
+        let len = ::core::cmp::min(self.len, buffer.len());
+        for byte in buffer[..len].iter_mut() {
+            *byte = self.buffer[self.head];
+            self.head = (self.head + 1) % DEBUG_BUFFER_LEN;
+        }
+        self.len -= len;
+        len
+    }
+
+    /// Total number of bytes dropped because the buffer was full.
+    pub fn dropped(&self) -> usize {
+        self.dropped
+    }
+
+    /// Counts a `debug!` message, returning its sequence number.
+    pub fn next_message(&mut self) -> usize {
+        self.count += 1;
+        self.count
+    }
+}
+
+impl Write for DebugBuffer {
+    fn write_str(&mut self, s: &str) -> ::core::fmt::Result {
+        if self.unreported > 0 && DEBUG_BUFFER_LEN - self.len >= 32 {
+            let unreported = self.unreported;
+            self.unreported = 0;
+            let _ = write!(self, "\r\n[{} debug bytes dropped]\r\n", unreported);
+        }
+        self.push(s.as_bytes());
+        Ok(())
+    }
+}
+
+/// Hands queued debug output to the console.
+pub struct DebugOutput;
+
+pub static DEBUG_OUTPUT: DebugOutput = DebugOutput;
+
+impl KernelOutput for DebugOutput {
+    fn fill(&self, buffer: &mut [u8]) -> usize {
+        unsafe { DEBUG_BUFFER.pop(buffer) }
+    }
+}
+
+/// Drains debug output through `console` from now on.
+pub unsafe fn set_debug_console(console: &'static XConsole<'static, mk66::uart::Uart>) {
+    console.set_kernel_output(&DEBUG_OUTPUT);
+    DEBUG_CONSOLE = Some(console);
+    debug_flush();
+}
+
+/// Starts sending queued debug output if the console is idle. Output
+/// queued before the console is set up waits for it.
+pub fn debug_flush() {
+    unsafe {
+        DEBUG_CONSOLE.map(|console| console.kernel_output_ready());
+    }
+}
+
+/// Writes out all queued debug output synchronously. Meant for panics and
+/// for tests that print a lot before the kernel loop runs.
+pub unsafe fn debug_flush_sync() {
+    let mut chunk = [0; 64];
+    loop {
+        let len = DEBUG_BUFFER.pop(&mut chunk);
+        if len == 0 {
+            break;
+        }
+        let _ = WRITER.write_str(::core::str::from_utf8_unchecked(&chunk[..len]));
+    }
+}
+
 #[cfg(not(test))]
 #[no_mangle]
 #[allow(unused_variables)]
 #[lang="panic_fmt"]
 pub unsafe extern "C" fn panic_fmt(args: Arguments, file: &'static str, line: u32) -> ! {
+    debug_flush_sync();
     let writer = &mut WRITER;
 
     // blink the panic signal
@@ -52,8 +177,9 @@ macro_rules! print {
         ($($arg:tt)*) => (
             {
                 use core::fmt::write;
-                let writer = unsafe { &mut $crate::io::WRITER };
+                let writer = unsafe { &mut $crate::io::DEBUG_BUFFER };
                 let _ = write(writer, format_args!($($arg)*));
+                $crate::io::debug_flush();
             }
         );
 }
@@ -63,3 +189,20 @@ macro_rules! println {
         ($fmt:expr) => (print!(concat!($fmt, "\n")));
             ($fmt:expr, $($arg:tt)*) => (print!(concat!($fmt, "\n"), $($arg)*));
 }
+
+#[macro_export]
+macro_rules! debug {
+        () => (debug!(""));
+        ($fmt:expr) => (debug!("{}", $fmt));
+        ($fmt:expr, $($arg:tt)*) => (
+            {
+                use core::fmt::write;
+                let writer = unsafe { &mut $crate::io::DEBUG_BUFFER };
+                let count = writer.next_message();
+                let _ = write(writer, format_args!("TOCK_DEBUG({}): {}:{}: ", count, file!(), line!()));
+                let _ = write(writer, format_args!($fmt, $($arg)*));
+                let _ = write(writer, format_args!("\r\n"));
+                $crate::io::debug_flush();
+            }
+        );
+}
@@ -4,7 +4,7 @@
 
 extern crate capsules;
 
-#[macro_use(debug, static_init, register_bitfields, register_bitmasks)]
+#[macro_use(static_init, register_bitfields, register_bitmasks)]
 extern crate kernel;
 
 #[allow(dead_code)]
@@ -24,6 +24,20 @@ mod components;
 
 pub mod xconsole;
 
+pub mod xmodem;
+
+mod smartcard;
+
+mod halfduplex;
+
+mod process_console;
+
+mod supervisor;
+
+mod reset;
+
+mod ewm;
+
 #[allow(dead_code)]
 mod pins;
 
@@ -37,6 +51,11 @@ struct Teensy {
     alarm: <AlarmComponent as Component>::Output,
     spi: <VirtualSpiComponent as Component>::Output,
     rng: <RngaComponent as Component>::Output,
+    xmodem: <XModemComponent as Component>::Output,
+    reset: <ResetReasonComponent as Component>::Output,
+    ewm: <EwmComponent as Component>::Output,
+    halfduplex: <HalfDuplexComponent as Component>::Output,
+    smartcard: Option<<SmartCardComponent as Component>::Output>,
     ipc: kernel::ipc::IPC,
 }
 
@@ -54,6 +73,11 @@ impl kernel::Platform for Teensy {
             capsules::led::DRIVER_NUM => f(Some(self.led)),
 
             capsules::rng::DRIVER_NUM => f(Some(self.rng)),
+            xmodem::DRIVER_NUM => f(Some(self.xmodem)),
+            reset::DRIVER_NUM => f(Some(self.reset)),
+            ewm::DRIVER_NUM => f(Some(self.ewm)),
+            halfduplex::DRIVER_NUM => f(Some(self.halfduplex)),
+            smartcard::DRIVER_NUM => f(self.smartcard.map(|d| d as &kernel::Driver)),
 
             kernel::ipc::DRIVER_NUM => f(Some(&self.ipc)),
             _ => f(None),
@@ -61,6 +85,104 @@ impl kernel::Platform for Teensy {
     }
 }
 
+// Number of processes that can run at the same time.
+const NUM_PROCS: usize = 4;
+
+// Memory each process may use, in load order. Together they fill
+// APP_MEMORY, and each is a power of two so the MPU can cover it.
+const APP_MEMORY_LIMITS: [usize; NUM_PROCS] = [64 << 10, 64 << 10, 32 << 10, 32 << 10];
+
+// How the kernel responds when a process faults. Panic would stop the board
+// at the first fault. The supervisor takes a faulted process out of the
+// process table whatever the kernel did with it, and recreates it after a
+// backoff.
+const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Restart;
+
+// 120 MHz from the PLL, the fastest the chip runs in RUN. Bus runs at
+// 60 MHz, FlexBus at 40 MHz and flash at 24 MHz.
+const CLOCKS: mk66::clock::ClockConfig = mk66::clock::ClockConfig {
+    xtal: mk66::mcg::xtals::Teensy16MHz,
+    source: mk66::clock::Source::Pll,
+    core_hz: 120_000_000,
+    bus_hz: 60_000_000,
+    flexbus_hz: 50_000_000,
+    flash_hz: 28_000_000,
+};
+
+// Set to run at 180 MHz instead, which puts the chip in HSRUN, where flash
+// can't be programmed and the chip can't sleep, so an idle kernel spins at
+// full power. Bus and FlexBus run at 60 MHz and flash at 25.7 MHz.
+const HSRUN: bool = false;
+
+const HSRUN_CLOCKS: mk66::clock::ClockConfig = mk66::clock::ClockConfig {
+    xtal: mk66::mcg::xtals::Teensy16MHz,
+    source: mk66::clock::Source::Pll,
+    core_hz: 180_000_000,
+    bus_hz: 60_000_000,
+    flexbus_hz: 60_000_000,
+    flash_hz: 28_000_000,
+};
+
+// Interrupt handlers run from the kernel loop, and when several are pending
+// the most urgent runs first, 0 being the most urgent. Priorities only order
+// that queue: a handler never preempts another. Everything starts at
+// DEFAULT_INTERRUPT_PRIORITY. UART receive comes first, since the receive
+// FIFO overflows within a few bytes at high baud rates, and SPI completion
+// comes last, since the transfer just waits.
+const DEFAULT_INTERRUPT_PRIORITY: u8 = 8;
+const INTERRUPT_PRIORITIES: [(mk66::nvic::NvicIdx, u8); 15] = [
+    (mk66::nvic::NvicIdx::UART0, 1),
+    (mk66::nvic::NvicIdx::UART1, 1),
+    (mk66::nvic::NvicIdx::UART2, 1),
+    (mk66::nvic::NvicIdx::UART4, 1),
+    (mk66::nvic::NvicIdx::PCMA, 2),
+    (mk66::nvic::NvicIdx::PCMB, 2),
+    (mk66::nvic::NvicIdx::PCMC, 2),
+    (mk66::nvic::NvicIdx::PCMD, 2),
+    (mk66::nvic::NvicIdx::PCME, 2),
+    (mk66::nvic::NvicIdx::PIT2, 3),
+    (mk66::nvic::NvicIdx::LLWU, 3),
+    (mk66::nvic::NvicIdx::RNG, 4),
+    (mk66::nvic::NvicIdx::SPI0, 12),
+    (mk66::nvic::NvicIdx::SPI1, 12),
+    (mk66::nvic::NvicIdx::SPI2, 12),
+];
+
+// RS-485 on UART1, with the transceiver's driver enable on pin 22.
+// RS485_ECHO says whether the transceiver's receiver stays enabled while it
+// transmits, as it does with RE tied low.
+const RS485_BAUD_RATE: u32 = 115200;
+const RS485_ECHO: bool = true;
+
+// Set if a smartcard is wired to UART0 (pins 0 and 1 tied to the card's
+// I/O line) with RST on pin 2 and a SMARTCARD_CLOCK_HZ clock from the
+// reader. The console then moves to UART4, on pins 33 (TX) and 34 (RX).
+const SMARTCARD: bool = false;
+const SMARTCARD_CLOCK_HZ: u32 = 4_000_000;
+
+// Resets the board if the kernel loop stops for a second.
+const WATCHDOG: mk66::wdog::Config = mk66::wdog::Config {
+    clock: mk66::wdog::ClockSource::Lpo,
+    timeout_ms: 1000,
+};
+
+// The supervisor app must service the EWM between 100 ms and 2 s after the
+// previous service once it has claimed it.
+const EWM: mk66::ewm::Config = mk66::ewm::Config {
+    window_min_ms: 100,
+    window_max_ms: 2000,
+    input: None,
+};
+
+static mut PROCESSES: [Option<&'static mut kernel::procs::Process<'static>>; NUM_PROCS] =
+    [None, None, None, None];
+
+static mut LOADED_APPS: [supervisor::LoadedApp; NUM_PROCS] = [supervisor::LoadedApp::empty(); NUM_PROCS];
+
+// Processes stopped from the process console, by index.
+static mut STOPPED_PROCESSES: [Option<&'static mut kernel::procs::Process<'static>>; NUM_PROCS] =
+    [None, None, None, None];
+
 #[link_section = ".flashconfig"]
 #[no_mangle]
 pub static FLASH_CONFIG_BYTES: [u8; 16] = [
@@ -70,20 +192,40 @@ pub static FLASH_CONFIG_BYTES: [u8; 16] = [
 
 #[no_mangle]
 pub unsafe fn reset_handler() {
-    // Disable the watchdog.
+    // Disable the watchdog until the kernel loop is running to refresh it.
     mk66::wdog::stop();
 
     // Relocate the text and data segments.
     mk66::init();
 
     // Configure the system clock.
-    mk66::clock::configure(120);
+    let clocks = if HSRUN { HSRUN_CLOCKS } else { CLOCKS };
+    if let Err(err) = mk66::clock::configure(clocks) {
+        panic!("Invalid clock configuration: {:?}", err);
+    }
 
     // Enable the Port Control and Interrupt clocks.
     use mk66::sim::Clock;
     mk66::sim::clocks::PORTABCDE.enable();
 
     let (gpio_pins, led_pins) = pins::configure_all_pins();
+
+    // After a wakeup from VLLS the pins stay latched until released.
+    mk66::llwu::recover_from_vlls();
+
+    let console_uart = if SMARTCARD {
+        pins::configure_uart4_pins();
+        &mk66::uart::UART4
+    } else {
+        &mk66::uart::UART0
+    };
+    io::set_console_uart(console_uart);
+
+    // Nothing drains debug output until the console is up, so write the
+    // boot report out directly.
+    report_boot();
+    io::debug_flush_sync();
+
     let gpio = GpioComponent::new()
                              .dependency(gpio_pins)
                              .finalize().unwrap();
@@ -91,9 +233,30 @@ pub unsafe fn reset_handler() {
                            .dependency(led_pins)
                            .finalize().unwrap();
     let spi = VirtualSpiComponent::new().finalize().unwrap();
-    let alarm = AlarmComponent::new().finalize().unwrap();
-    let xconsole = XConsoleComponent::new().finalize().unwrap();
+    let alarm_mux = AlarmMuxComponent::new().finalize().unwrap();
+    let alarm = AlarmComponent::new()
+                               .dependency(alarm_mux)
+                               .finalize().unwrap();
+    let xconsole = XConsoleComponent::new(console_uart).finalize().unwrap();
     let rng = RngaComponent::new().finalize().unwrap();
+    let xmodem = XModemComponent::new()
+                                 .dependency((xconsole, alarm_mux))
+                                 .finalize().unwrap();
+    let reset = ResetReasonComponent::new().finalize().unwrap();
+    let ewm = EwmComponent::new(EWM).finalize().unwrap();
+    pins::configure_rs485_pins();
+    let halfduplex = HalfDuplexComponent::new(RS485_BAUD_RATE,
+                                              mk66::uart::TransmitEnablePolarity::ActiveHigh,
+                                              RS485_ECHO)
+                                         .dependency(alarm_mux)
+                                         .finalize().unwrap();
+    let smartcard = if SMARTCARD {
+        SmartCardComponent::new(SMARTCARD_CLOCK_HZ)
+                           .dependency((alarm_mux, gpio_pins[2]))
+                           .finalize()
+    } else {
+        None
+    };
 
     let teensy = Teensy {
         xconsole: xconsole,
@@ -102,17 +265,80 @@ pub unsafe fn reset_handler() {
         alarm: alarm,
         spi: spi,
         rng: rng,
+        xmodem: xmodem,
+        reset: reset,
+        ewm: ewm,
+        halfduplex: halfduplex,
+        smartcard: smartcard,
         ipc: kernel::ipc::IPC::new(),
     };
 
     let mut chip = mk66::chip::MK66::new();
+    mk66::nvic::set_all_priorities(DEFAULT_INTERRUPT_PRIORITY);
+    for &(interrupt, priority) in INTERRUPT_PRIORITIES.iter() {
+        mk66::nvic::set_priority(interrupt, priority);
+    }
 
     if tests::TEST {
         tests::test();
     }
-    kernel::kernel_loop(&teensy, &mut chip, load_processes(), Some(&teensy.ipc));
+    let processes = load_processes();
+    let supervisor = SupervisorComponent::new(FAULT_RESPONSE)
+                                         .dependency((alarm_mux,
+                                                      &mut STOPPED_PROCESSES[..],
+                                                      &mut LOADED_APPS[..]))
+                                         .finalize().unwrap();
+
+    pins::configure_uart2_pins();
+    ProcessConsoleComponent::new(&mk66::uart::UART2, 115200)
+                            .dependency(supervisor as &'static supervisor::ProcessControl)
+                            .finalize().unwrap();
+
+    mk66::wdog::configure(WATCHDOG);
+    kernel::kernel_loop(&teensy, &mut chip, processes, Some(&teensy.ipc));
 }
 
+/// Reports why the board reset and, if the kernel faulted before the reset,
+/// the crash dump it saved.
+fn report_boot() {
+    let status = mk66::rcm::reset_status();
+    print!("Reset ({:#06x}):", status.raw());
+    for cause in status.causes() {
+        print!(" {}", cause.name());
+    }
+    println!("");
+
+    // The LPO is only accurate to a few percent, so only a clock that is
+    // clearly wrong is reported.
+    let configured = mk66::clock::core_clock_hz();
+    let measured = mk66::clock::measure_core_hz(mk66::clock::Reference::Lpo, 50);
+    println!("Core clock: {} Hz, measured {} Hz", configured, measured);
+    if measured < configured / 100 * 95 || measured > configured / 100 * 105 {
+        println!("Core clock is off by more than 5%");
+    }
+
+    let dump = match mk66::crash::take_previous() {
+        Some(dump) => dump,
+        None => return,
+    };
+    println!("Previous boot ended in a kernel fault:");
+    println!("  pc   {:#010x}  lr   {:#010x}  xpsr  {:#010x}  sp   {:#010x}",
+             dump.pc(), dump.lr(), dump.xpsr(), dump.sp);
+    println!("  r0   {:#010x}  r1   {:#010x}  r2    {:#010x}  r3   {:#010x}  r12 {:#010x}",
+             dump.stacked[0], dump.stacked[1], dump.stacked[2], dump.stacked[3], dump.stacked[4]);
+    println!("  cfsr {:#010x}  hfsr {:#010x}  mmfar {:#010x}  bfar {:#010x}",
+             dump.cfsr, dump.hfsr, dump.mmfar, dump.bfar);
+    println!("  stack:");
+    unsafe { io::debug_flush_sync(); }
+    for (i, words) in dump.stack[..dump.stack_len as usize].chunks(4).enumerate() {
+        print!("  {:#010x}:", dump.sp + 16 * i as u32);
+        for word in words {
+            print!(" {:08x}", word);
+        }
+        println!("");
+        unsafe { io::debug_flush_sync(); }
+    }
+}
 
 unsafe fn load_processes() -> &'static mut [Option<&'static mut kernel::procs::Process<'static>>] {
     extern "C" {
@@ -120,21 +346,17 @@ unsafe fn load_processes() -> &'static mut [Option<&'static mut kernel::procs::P
         static _sapps: u8;
     }
 
-    const NUM_PROCS: usize = 1;
-
-    // Total memory allocated to the processes
+    // Total memory allocated to the processes: all of the upper SRAM chunk.
+    // See chip_layout.ld.
     #[link_section = ".app_memory"]
-    static mut APP_MEMORY: [u8; 1 << 17] = [0; 1 << 17];
-
-    // How the kernel responds when a process faults
-    const FAULT_RESPONSE: kernel::procs::FaultResponse = kernel::procs::FaultResponse::Panic;
-
-    static mut PROCESSES: [Option<&'static mut kernel::procs::Process<'static>>; NUM_PROCS] = [None];
+    static mut APP_MEMORY: [u8; 192 << 10] = [0; 192 << 10];
 
-    kernel::procs::load_processes(
+    supervisor::load_processes(
         &_sapps as *const u8,
         &mut APP_MEMORY,
+        &APP_MEMORY_LIMITS,
         &mut PROCESSES,
+        &mut LOADED_APPS,
         FAULT_RESPONSE,
     );
 
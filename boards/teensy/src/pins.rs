@@ -44,15 +44,16 @@ pub unsafe fn configure_all_pins() -> (&'static [PinHandle],
     PB17.claim_as(UART0_TX);
     PB16.claim_as(UART0_RX);
 
-    // SPI0
-    PC04.release_claim();
+    // SPI0. Chip select is on pin 15 (PCS4), since pin 10 (PCS0) is UART1
+    // TX for RS-485.
+    PC00.release_claim();
     PC06.release_claim();
     PC07.release_claim();
     PA15.release_claim();
     PC06.claim_as(SPI0_MOSI);
     PC07.claim_as(SPI0_MISO);
     PA15.claim_as(SPI0_SCK);
-    PC04.claim_as(SPI0_CS0);
+    PC00.claim_as(SPI0_CS4);
 
     // SPI1
     PD05.release_claim();
@@ -79,3 +80,75 @@ pub unsafe fn configure_all_pins() -> (&'static [PinHandle],
     (gpio_pins, led_pins)
 }
 
+
+/// Muxes UART1 onto Teensy pins 9 (RX2), 10 (TX2) and 22 (transmitter
+/// enable). Pin 10 is also SPI0 CS0, which is why SPI0 uses CS4.
+pub unsafe fn configure_rs485_pins() {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PC03.release_claim();
+    PC04.release_claim();
+    PC01.release_claim();
+    PC03.claim_as(UART1_RX);
+    PC04.claim_as(UART1_TX);
+    PC01.claim_as(UART1_RTS);
+}
+
+/// Muxes UART1 onto Teensy pins 9 (RX2) and 10 (TX2). Pin 10 is also SPI0
+/// CS0, so SPI0 must use another chip select.
+pub unsafe fn configure_uart1_pins() {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PC03.release_claim();
+    PC04.release_claim();
+    PC03.claim_as(UART1_RX);
+    PC04.claim_as(UART1_TX);
+}
+
+/// Muxes UART2 onto Teensy pins 7 (RX3) and 8 (TX3).
+pub unsafe fn configure_uart2_pins() {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PD02.release_claim();
+    PD03.release_claim();
+    PD02.claim_as(UART2_RX);
+    PD03.claim_as(UART2_TX);
+}
+
+/// Muxes UART4 onto Teensy pins 34 (RX5) and 33 (TX5).
+pub unsafe fn configure_uart4_pins() {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PE24.release_claim();
+    PE25.release_claim();
+    PE25.claim_as(UART4_RX);
+    PE24.claim_as(UART4_TX);
+}
+
+/// Muxes EWM_OUT onto Teensy pin 20 and, if `input` is set, EWM_IN onto
+/// pin 6. Pin 20 is also SPI1 SCK, so SPI1 must not be used.
+pub unsafe fn configure_ewm_pins(input: bool) {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PD05.release_claim();
+    PD05.claim_as(EWM_OUT);
+    if input {
+        PD04.release_claim();
+        PD04.claim_as(EWM_IN);
+    }
+}
+
+/// Muxes CLKOUT onto Teensy pin 9, for checking clocks with a scope. Pin 9
+/// is also UART1 RX.
+pub unsafe fn configure_clkout_pin() {
+    use mk66::gpio::functions::*;
+    use mk66::gpio::*;
+
+    PC03.release_claim();
+    PC03.claim_as(CLKOUT);
+}
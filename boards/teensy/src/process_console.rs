@@ -0,0 +1,271 @@
+//! A kernel shell for inspecting and controlling processes over a UART.
+//!
+//! The console runs entirely in the kernel, so it keeps working when apps
+//! hang. It is meant for a UART that is not shared with XConsole, such as
+//! UART2 (see `pins::configure_uart2_pins`). Connect at the configured baud
+//! rate and type `help`.
+//!
+//! Processes are stopped and started through the supervisor, which owns
+//! changes to the process table. Stopping a process takes it out of the
+//! table, so it is no longer scheduled and callbacks for it are dropped.
+//! Starting it puts it back where it left off.
+//!
+//! Setup
+//! -----
+//!
+//! ```rust
+//! pins::configure_uart2_pins();
+//! let process_console = ProcessConsoleComponent::new(&mk66::uart::UART2, 115200)
+//!                           .dependency(supervisor)
+//!                           .finalize().unwrap();
+//! ```
+
+use core::cell::Cell;
+use core::fmt::{self, Write};
+use core::str;
+use kernel::common::cells::TakeCell;
+use kernel::ReturnCode;
+use kernel::hil::uart::{self, UART, Client};
+use kernel::procs::{Process, State};
+use supervisor::ProcessControl;
+
+pub static mut WRITE_BUF: [u8; 2048] = [0; 2048];
+pub static mut PENDING_BUF: [u8; 2048] = [0; 2048];
+pub static mut READ_BUF: [u8; 1] = [0; 1];
+pub static mut LINE_BUF: [u8; 32] = [0; 32];
+
+const PROMPT: &'static str = "tock> ";
+
+/// Formats into a byte buffer, dropping what does not fit.
+struct BufferWriter<'b> {
+    buffer: &'b mut [u8],
+    len: usize,
+}
+
+impl<'b> Write for BufferWriter<'b> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let start = self.len;
+        let len = ::core::cmp::min(s.len(), self.buffer.len() - start);
+        self.buffer[start..start + len].copy_from_slice(&s.as_bytes()[..len]);
+        self.len += len;
+        Ok(())
+    }
+}
+
+pub struct ProcessConsole<'a, U: UART + 'a> {
+    uart: &'a U,
+    baud_rate: u32,
+    processes: &'a ProcessControl,
+    tx_buffer: TakeCell<'static, [u8]>,
+    // Output waiting for the transmit buffer to come back
+    pending: TakeCell<'static, [u8]>,
+    pending_len: Cell<usize>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    line: TakeCell<'static, [u8]>,
+    line_len: Cell<usize>,
+}
+
+impl<'a, U: UART> ProcessConsole<'a, U> {
+    pub fn new(uart: &'a U,
+               baud_rate: u32,
+               processes: &'a ProcessControl,
+               tx_buffer: &'static mut [u8],
+               pending_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
+               line: &'static mut [u8])
+               -> ProcessConsole<'a, U> {
+        ProcessConsole {
+            uart: uart,
+            baud_rate: baud_rate,
+            processes: processes,
+            tx_buffer: TakeCell::new(tx_buffer),
+            pending: TakeCell::new(pending_buffer),
+            pending_len: Cell::new(0),
+            rx_buffer: TakeCell::new(rx_buffer),
+            line: TakeCell::new(line),
+            line_len: Cell::new(0),
+        }
+    }
+
+    pub fn initialize(&self) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.baud_rate,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::None,
+            hw_flow_control: false,
+        });
+        self.rx_buffer.take().map(|buffer| {
+            self.uart.receive(buffer, 1);
+        });
+        self.output(|writer| {
+            let _ = write!(writer, "\r\nTock process console\r\n{}", PROMPT);
+        });
+    }
+
+    /// Formats output after anything still waiting to be sent, and sends it
+    /// as soon as the transmit buffer is free. Output that does not fit in
+    /// the pending buffer is dropped.
+    fn output<F: FnOnce(&mut BufferWriter)>(&self, f: F) {
+        self.pending.map(|pending| {
+            let start = self.pending_len.get();
+            let mut writer = BufferWriter { buffer: &mut pending[start..], len: 0 };
+            f(&mut writer);
+            self.pending_len.set(start + writer.len);
+        });
+        self.flush();
+    }
+
+    /// Sends the pending output if the previous transmission is done.
+    fn flush(&self) {
+        let len = self.pending_len.get();
+        if len == 0 {
+            return;
+        }
+        self.tx_buffer.take().map(|buffer| {
+            self.pending.map(|pending| buffer[..len].copy_from_slice(&pending[..len]));
+            self.pending_len.set(0);
+            self.uart.transmit(buffer, len);
+        });
+    }
+
+    fn received(&self, byte: u8) {
+        match byte {
+            b'\r' | b'\n' => {
+                let len = self.line_len.get();
+                self.line_len.set(0);
+                self.line.map(|line| {
+                    let command = str::from_utf8(&line[..len]).unwrap_or("");
+                    self.execute(command);
+                });
+            }
+            0x08 | 0x7F => {
+                if self.line_len.get() > 0 {
+                    self.line_len.set(self.line_len.get() - 1);
+                    self.output(|writer| { let _ = writer.write_str("\x08 \x08"); });
+                }
+            }
+            0x20...0x7E => {
+                let len = self.line_len.get();
+                let stored = self.line.map_or(false, |line| {
+                    if len < line.len() {
+                        line[len] = byte;
+                        true
+                    } else {
+                        false
+                    }
+                });
+                if stored {
+                    self.line_len.set(len + 1);
+                    self.output(|writer| { let _ = writer.write_char(byte as char); });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn execute(&self, command: &str) {
+        let mut words = command.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let index = words.next().and_then(|word| word.parse::<usize>().ok());
+
+        self.output(|writer| {
+            let _ = writer.write_str("\r\n");
+            match (name, index) {
+                ("", _) => {}
+                ("help", _) => {
+                    let _ = writer.write_str("Commands:\r\n\
+                                              \x20 list        processes and memory use\r\n\
+                                              \x20 stop <n>    stop scheduling process n\r\n\
+                                              \x20 start <n>   resume process n\r\n\
+                                              \x20 dump <n>    fault state and statistics\r\n");
+                }
+                ("list", _) => self.list(writer),
+                ("stop", Some(index)) => self.stop(writer, index),
+                ("start", Some(index)) => self.start(writer, index),
+                ("dump", Some(index)) => self.dump(writer, index),
+                ("stop", None) | ("start", None) | ("dump", None) => {
+                    let _ = writer.write_str("Missing process number\r\n");
+                }
+                _ => {
+                    let _ = write!(writer, "Unknown command: {}\r\n", name);
+                }
+            }
+            let _ = writer.write_str(PROMPT);
+        });
+    }
+
+    fn list(&self, writer: &mut BufferWriter) {
+        let _ = writer.write_str(" n  Name                 State     App   Grant  Total\r\n");
+        for i in 0..self.processes.len() {
+            self.processes.inspect(i, &mut |process: &mut Process<'static>, stopped| {
+                let state = match (stopped, process.current_state()) {
+                    (true, _) => "Stopped",
+                    (false, State::Running) => "Running",
+                    (false, State::Yielded) => "Yielded",
+                    (false, State::Fault) => "Fault",
+                };
+
+                let start = process.mem_start() as usize;
+                let end = process.mem_end() as usize;
+                let app = process.app_memory_break() as usize - start;
+                let grant = end - process.kernel_memory_break() as usize;
+                let _ = write!(writer, "{:2}  {:20} {:8} {:6} {:6} {:6}\r\n",
+                               i, process.package_name, state, app, grant, end - start);
+            });
+        }
+    }
+
+    fn stop(&self, writer: &mut BufferWriter, index: usize) {
+        match self.processes.stop(index) {
+            ReturnCode::SUCCESS => {
+                self.processes.inspect(index, &mut |process: &mut Process<'static>, _| {
+                    let _ = write!(writer, "Stopped {}\r\n", process.package_name);
+                });
+            }
+            _ => {
+                let _ = write!(writer, "No running process {}\r\n", index);
+            }
+        }
+    }
+
+    fn start(&self, writer: &mut BufferWriter, index: usize) {
+        match self.processes.start(index) {
+            ReturnCode::SUCCESS => {
+                self.processes.inspect(index, &mut |process: &mut Process<'static>, _| {
+                    let _ = write!(writer, "Started {}\r\n", process.package_name);
+                });
+            }
+            _ => {
+                let _ = write!(writer, "No stopped process {}\r\n", index);
+            }
+        }
+    }
+
+    fn dump(&self, writer: &mut BufferWriter, index: usize) {
+        let found = self.processes.inspect(index, &mut |process: &mut Process<'static>, _| {
+            unsafe {
+                process.fault_str(writer);
+                process.statistics_str(writer);
+            }
+        });
+        if !found {
+            let _ = write!(writer, "No process {}\r\n", index);
+        }
+    }
+}
+
+impl<'a, U: UART> Client for ProcessConsole<'a, U> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        self.flush();
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, _error: uart::Error) {
+        let byte = rx_buffer[0];
+        self.uart.receive(rx_buffer, 1);
+
+        if rx_len > 0 {
+            self.received(byte);
+        }
+    }
+}
@@ -0,0 +1,39 @@
+//! Lets apps find out why the board last reset.
+//!
+//! Commands
+//! --------
+//!
+//! 0: check if present.
+//! 1: raw reset status, SRS1 in bits 15-8 and SRS0 in bits 7-0.
+//! 2: primary cause, as the bit number of that cause in the raw status.
+//!    ENODEVICE if no cause is recorded.
+
+use kernel::{AppId, Driver, ReturnCode};
+use mk66::rcm::ResetStatus;
+
+pub const DRIVER_NUM: usize = 0x00090004;
+
+pub struct ResetReason {
+    status: ResetStatus,
+}
+
+impl ResetReason {
+    pub fn new(status: ResetStatus) -> ResetReason {
+        ResetReason { status: status }
+    }
+}
+
+impl Driver for ResetReason {
+    fn command(&self, command_num: usize, _: usize, _: usize, _: AppId) -> ReturnCode {
+        match command_num {
+            0 => ReturnCode::SUCCESS,
+            1 => ReturnCode::SuccessWithValue { value: self.status.raw() as usize },
+            2 => {
+                self.status.primary().map_or(ReturnCode::ENODEVICE, |cause| {
+                    ReturnCode::SuccessWithValue { value: cause.mask().trailing_zeros() as usize }
+                })
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+}
@@ -0,0 +1,752 @@
+//! Provides userspace with access to an ISO-7816 smartcard on UART0.
+//!
+//! The K66 UART handles the character level of ISO-7816 in hardware: guard
+//! times, waiting times, parity NACKs and retransmission. This capsule runs
+//! the transmission protocols on top of it: it resets the card and reads its
+//! answer to reset (ATR), then exchanges APDUs using either T=0 (procedure
+//! bytes) or T=1 (I-, R- and S-blocks with LRC checking).
+//!
+//! The card clock is not generated here; the board must supply it and pass
+//! its frequency in so the UART can run at one character per ETU. The card
+//! I/O line is wired to both UART0_TX and UART0_RX. The card sends its
+//! bytes without waiting for us, so they are buffered in a receive ring
+//! between the single-byte receives of the protocols.
+//!
+//! Usage
+//! -----
+//!
+//! ```c
+//! subscribe(SMARTCARD_DRIVER_NUM, 0, callback);  // (status, len, 0)
+//! allow(SMARTCARD_DRIVER_NUM, 0, response, 260); // ATR and responses
+//! command(SMARTCARD_DRIVER_NUM, 1, 0, 0);        // reset, read ATR
+//! allow(SMARTCARD_DRIVER_NUM, 1, apdu, apdu_len);
+//! command(SMARTCARD_DRIVER_NUM, 2, apdu_len, 0); // exchange an APDU
+//! ```
+//!
+//! Switching to T=1 with command 3 assumes the card runs in specific mode
+//! (or that the application performed PPS itself); no PPS exchange is done.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Grant, Callback, Shared, Driver, ReturnCode};
+use kernel::common::cells::TakeCell;
+use kernel::hil::gpio::Pin;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::uart::{self, UART, Client};
+use mk66::uart::{Uart, Iso7816Client, Iso7816Event, Iso7816Params, Iso7816Protocol};
+
+pub const DRIVER_NUM: usize = 0x00090001;
+
+pub static mut TX_BUF: [u8; 272] = [0; 272];
+pub static mut RX_BUF: [u8; 272] = [0; 272];
+pub static mut APDU_BUF: [u8; 272] = [0; 272];
+pub static mut RESPONSE_BUF: [u8; 272] = [0; 272];
+pub static mut RING_BUF: [u8; 272] = [0; 272];
+
+/// Default clock rate conversion factor F and baud rate adjustment D.
+const DEFAULT_F: u32 = 372;
+const DEFAULT_D: u32 = 1;
+
+/// Maximum length of an answer to reset.
+const ATR_MAX_LEN: usize = 33;
+
+/// Default T=1 maximum information field size of the card.
+const DEFAULT_IFSC: usize = 32;
+
+/// Number of times a T=1 block is retransmitted before giving up.
+const T1_RETRIES: usize = 3;
+
+/// Card clock cycles RST is held low for during a cold reset.
+const RESET_CYCLES: u32 = 400;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    Reset,
+    Atr,
+    T0Procedure,
+    T0Data,
+    T0Sw2,
+    T1Prologue,
+    T1Body,
+    Failed(ReturnCode),
+}
+
+pub struct App {
+    callback: Option<Callback>,
+    read_buffer: Option<AppSlice<Shared, u8>>,
+    write_buffer: Option<AppSlice<Shared, u8>>,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            read_buffer: None,
+            write_buffer: None,
+        }
+    }
+}
+
+pub struct SmartCard<'a, A: Alarm + 'a> {
+    uart: &'a Uart,
+    reset_pin: &'a Pin,
+    alarm: &'a A,
+    card_clock_hz: u32,
+    apps: Grant<App>,
+    current_app: Cell<Option<AppId>>,
+    state: Cell<State>,
+    protocol: Cell<Iso7816Protocol>,
+    params: Cell<Iso7816Params>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    rx_buffer: TakeCell<'static, [u8]>,
+    next_rx_len: Cell<usize>,
+    // The command APDU being exchanged
+    apdu: TakeCell<'static, [u8]>,
+    apdu_len: Cell<usize>,
+    // The ATR or response APDU being assembled
+    response: TakeCell<'static, [u8]>,
+    response_len: Cell<usize>,
+    // T=0 exchange progress
+    t0_sent: Cell<usize>,
+    t0_outgoing: Cell<usize>,
+    t0_incoming: Cell<usize>,
+    // T=1 link state
+    t1_prologue: Cell<[u8; 3]>,
+    t1_send_seq: Cell<bool>,
+    t1_recv_seq: Cell<bool>,
+    /// Our last I-block has not been acknowledged by the card yet
+    t1_unacked: Cell<bool>,
+    t1_retries: Cell<usize>,
+    ifsc: Cell<usize>,
+}
+
+impl<'a, A: Alarm> SmartCard<'a, A> {
+    pub fn new(uart: &'a Uart,
+               reset_pin: &'a Pin,
+               alarm: &'a A,
+               card_clock_hz: u32,
+               tx_buffer: &'static mut [u8],
+               rx_buffer: &'static mut [u8],
+               apdu_buffer: &'static mut [u8],
+               response_buffer: &'static mut [u8],
+               container: Grant<App>)
+               -> SmartCard<'a, A> {
+        SmartCard {
+            uart: uart,
+            reset_pin: reset_pin,
+            alarm: alarm,
+            card_clock_hz: card_clock_hz,
+            apps: container,
+            current_app: Cell::new(None),
+            state: Cell::new(State::Idle),
+            protocol: Cell::new(Iso7816Protocol::T0),
+            params: Cell::new(Iso7816Params::default()),
+            tx_buffer: TakeCell::new(tx_buffer),
+            rx_buffer: TakeCell::new(rx_buffer),
+            next_rx_len: Cell::new(0),
+            apdu: TakeCell::new(apdu_buffer),
+            apdu_len: Cell::new(0),
+            response: TakeCell::new(response_buffer),
+            response_len: Cell::new(0),
+            t0_sent: Cell::new(0),
+            t0_outgoing: Cell::new(0),
+            t0_incoming: Cell::new(0),
+            t1_prologue: Cell::new([0; 3]),
+            t1_send_seq: Cell::new(false),
+            t1_recv_seq: Cell::new(false),
+            t1_unacked: Cell::new(false),
+            t1_retries: Cell::new(0),
+            ifsc: Cell::new(DEFAULT_IFSC),
+        }
+    }
+
+    pub fn initialize(&self, ring_buffer: &'static mut [u8]) {
+        self.uart.init(uart::UARTParams {
+            baud_rate: self.card_clock_hz * DEFAULT_D / DEFAULT_F,
+            stop_bits: uart::StopBits::One,
+            parity: uart::Parity::Even,
+            hw_flow_control: false,
+        });
+        self.uart.enable_iso7816(&self.params.get());
+        self.uart.enable_rx_ring(ring_buffer);
+
+        self.reset_pin.make_output();
+        self.reset_pin.clear();
+    }
+
+    /// Starts a cold reset of the card. RST is held low for at least
+    /// `RESET_CYCLES` card clocks; the alarm then releases it and starts
+    /// reading the ATR.
+    fn reset(&self) {
+        let mut params = Iso7816Params::default();
+        params.protocol = Iso7816Protocol::T0;
+        self.params.set(params);
+        self.protocol.set(Iso7816Protocol::T0);
+        self.uart.enable_iso7816(&params);
+
+        self.reset_pin.clear();
+        self.state.set(State::Reset);
+        let ticks = RESET_CYCLES as u64 * A::Frequency::frequency() as u64 /
+                    self.card_clock_hz as u64 + 1;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks as u32));
+    }
+
+    /// Releases RST and starts reading the ATR. Anything left in the ring
+    /// from before the reset is not part of it.
+    fn release_reset(&self) {
+        self.uart.clear_rx_ring();
+        self.response_len.set(0);
+        self.state.set(State::Atr);
+        self.uart.detect_initial_character();
+        self.reset_pin.set();
+        self.receive(2);
+    }
+
+    /// Sets the extra guard time N as coded in TC1.
+    fn set_guard_time(&self, n: u8) {
+        let guard_time = extra_guard_time(n);
+        let mut params = self.params.get();
+        params.guard_time = guard_time;
+        self.params.set(params);
+        self.uart.set_iso7816_guard_time(guard_time);
+    }
+
+    fn set_protocol(&self, protocol: Iso7816Protocol) {
+        let mut params = self.params.get();
+        params.protocol = protocol;
+        self.params.set(params);
+        self.protocol.set(protocol);
+        self.t1_send_seq.set(false);
+        self.t1_recv_seq.set(false);
+        self.t1_unacked.set(false);
+        self.ifsc.set(DEFAULT_IFSC);
+        self.uart.enable_iso7816(&params);
+    }
+
+    fn receive(&self, len: usize) {
+        self.rx_buffer.take().map(|buffer| {
+            self.uart.receive(buffer, len);
+        });
+    }
+
+    /// Sends the first `len` bytes of the transmit buffer, then receives
+    /// `rx_len` bytes.
+    fn transmit(&self, len: usize, rx_len: usize) {
+        self.next_rx_len.set(rx_len);
+        self.tx_buffer.take().map(|buffer| {
+            self.uart.transmit(buffer, len);
+        });
+    }
+
+    fn append_response(&self, data: &[u8]) -> bool {
+        let start = self.response_len.get();
+        self.response.map_or(false, |response| {
+            if start + data.len() > response.len() {
+                return false;
+            }
+            response[start..start + data.len()].copy_from_slice(data);
+            self.response_len.set(start + data.len());
+            true
+        })
+    }
+
+    /// Ends the current operation, copying the response to the app.
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        let len = self.response_len.get();
+        self.current_app.get().map(|appid| {
+            self.current_app.set(None);
+            let _ = self.apps.enter(appid, |app, _| {
+                let mut copied = 0;
+                if result == ReturnCode::SUCCESS {
+                    self.response.map(|response| {
+                        app.read_buffer.as_mut().map(|dest| {
+                            copied = cmp::min(len, dest.len());
+                            dest.as_mut()[..copied].copy_from_slice(&response[..copied]);
+                        });
+                    });
+                }
+                app.callback.map(|mut cb| {
+                    cb.schedule(isize::from(result) as usize, copied, 0);
+                });
+            });
+        });
+    }
+
+    fn atr_complete(&self) {
+        let info = self.response.map_or(AtrInfo::default(), |response| {
+            parse_atr(&response[..self.response_len.get()])
+        });
+
+        self.set_guard_time(info.guard_time);
+        self.finish(ReturnCode::SUCCESS);
+    }
+
+    fn start_t0(&self, len: usize) -> ReturnCode {
+        if len < 4 {
+            return ReturnCode::EINVAL;
+        }
+
+        // Case 1 commands have no P3; send it as zero.
+        let header = self.apdu.map_or([0; 5], |apdu| {
+            [apdu[0], apdu[1], apdu[2], apdu[3], if len > 4 { apdu[4] } else { 0 }]
+        });
+
+        let (outgoing, incoming) = if len > 5 {
+            (cmp::min(len - 5, header[4] as usize), 0)
+        } else if len == 5 {
+            (0, if header[4] == 0 { 256 } else { header[4] as usize })
+        } else {
+            (0, 0)
+        };
+
+        self.t0_sent.set(0);
+        self.t0_outgoing.set(outgoing);
+        self.t0_incoming.set(incoming);
+        self.response_len.set(0);
+
+        self.tx_buffer.map(|buffer| buffer[..5].copy_from_slice(&header));
+        self.state.set(State::T0Procedure);
+        self.transmit(5, 1);
+        ReturnCode::SUCCESS
+    }
+
+    fn t0_procedure_byte(&self, pb: u8) {
+        let ins = self.apdu.map_or(0, |apdu| apdu[1]);
+        let sent = self.t0_sent.get();
+        let outgoing = self.t0_outgoing.get();
+        let remaining_in = self.t0_incoming.get() - cmp::min(self.t0_incoming.get(),
+                                                               self.response_len.get());
+
+        if pb == 0x60 {
+            // NULL: the card needs more time.
+            self.receive(1);
+        } else if pb == ins || pb == ins ^ 0xFF {
+            // ACK: transfer the remaining data, or a single byte.
+            let all = pb == ins;
+            if sent < outgoing {
+                let count = if all { outgoing - sent } else { 1 };
+                self.apdu.map(|apdu| {
+                    self.tx_buffer.map(|buffer| {
+                        buffer[..count].copy_from_slice(&apdu[5 + sent..5 + sent + count]);
+                    });
+                });
+                self.t0_sent.set(sent + count);
+                self.transmit(count, 1);
+            } else if remaining_in > 0 {
+                self.state.set(State::T0Data);
+                self.receive(if all { remaining_in } else { 1 });
+            } else {
+                self.receive(1);
+            }
+        } else if pb & 0xF0 == 0x60 || pb & 0xF0 == 0x90 {
+            // SW1, followed by SW2.
+            self.append_response(&[pb]);
+            self.state.set(State::T0Sw2);
+            self.receive(1);
+        } else {
+            self.finish(ReturnCode::FAIL);
+        }
+    }
+
+    fn start_t1(&self, len: usize) -> ReturnCode {
+        if len == 0 || len > self.ifsc.get() {
+            return ReturnCode::ESIZE;
+        }
+        self.response_len.set(0);
+        self.t1_retries.set(0);
+        self.t1_send_iblock();
+        ReturnCode::SUCCESS
+    }
+
+    fn t1_send_iblock(&self) {
+        let len = self.apdu_len.get();
+        let pcb = if self.t1_send_seq.get() { 0x40 } else { 0x00 };
+        self.apdu.map(|apdu| {
+            self.tx_buffer.map(|buffer| {
+                buffer[0] = 0; // NAD
+                buffer[1] = pcb;
+                buffer[2] = len as u8;
+                buffer[3..3 + len].copy_from_slice(&apdu[..len]);
+                buffer[3 + len] = lrc(&buffer[..3 + len]);
+            });
+        });
+        self.t1_unacked.set(true);
+        self.state.set(State::T1Prologue);
+        self.transmit(len + 4, 3);
+    }
+
+    fn t1_send_control(&self, pcb: u8, inf: Option<u8>) {
+        let len = self.tx_buffer.map_or(0, |buffer| {
+            buffer[0] = 0;
+            buffer[1] = pcb;
+            match inf {
+                Some(byte) => {
+                    buffer[2] = 1;
+                    buffer[3] = byte;
+                    buffer[4] = lrc(&buffer[..4]);
+                    5
+                }
+                None => {
+                    buffer[2] = 0;
+                    buffer[3] = lrc(&buffer[..3]);
+                    4
+                }
+            }
+        });
+        self.state.set(State::T1Prologue);
+        self.transmit(len, 3);
+    }
+
+    fn t1_receive_ready(&self, error: bool) -> u8 {
+        let nr = if self.t1_recv_seq.get() { 0x10 } else { 0x00 };
+        0x80 | nr | if error { 0x01 } else { 0x00 }
+    }
+
+    fn t1_block(&self, body: &[u8]) {
+        let prologue = self.t1_prologue.get();
+        let pcb = prologue[1];
+        let inf = &body[..body.len() - 1];
+
+        let valid = lrc(&prologue) ^ lrc(inf) == body[body.len() - 1];
+        if !valid {
+            if self.t1_retries.get() >= T1_RETRIES {
+                self.finish(ReturnCode::FAIL);
+            } else {
+                self.t1_retries.set(self.t1_retries.get() + 1);
+                let rblock = self.t1_receive_ready(true);
+                self.t1_send_control(rblock, None);
+            }
+            return;
+        }
+
+        if pcb & 0x80 == 0 {
+            // I-block carrying response data. The first one acknowledges
+            // our block; the rest of a chain don't.
+            if self.t1_unacked.get() {
+                self.t1_unacked.set(false);
+                self.t1_send_seq.set(!self.t1_send_seq.get());
+            }
+            self.t1_recv_seq.set(!self.t1_recv_seq.get());
+            self.t1_retries.set(0);
+            if !self.append_response(inf) {
+                self.finish(ReturnCode::ESIZE);
+            } else if pcb & 0x20 != 0 {
+                // More data is chained; acknowledge and wait for the rest.
+                let rblock = self.t1_receive_ready(false);
+                self.t1_send_control(rblock, None);
+            } else {
+                self.finish(ReturnCode::SUCCESS);
+            }
+        } else if pcb & 0xC0 == 0x80 {
+            // R-block: the card wants our last block again.
+            if self.t1_retries.get() >= T1_RETRIES {
+                self.finish(ReturnCode::FAIL);
+            } else {
+                self.t1_retries.set(self.t1_retries.get() + 1);
+                self.t1_send_iblock();
+            }
+        } else {
+            // S-block requests are answered with the matching response.
+            match pcb & 0x3F {
+                0x01 if inf.len() == 1 => {
+                    self.ifsc.set(inf[0] as usize);
+                    self.t1_send_control(0xE1, Some(inf[0]));
+                }
+                0x03 if inf.len() == 1 => {
+                    self.t1_send_control(0xE3, Some(inf[0]));
+                }
+                _ => self.finish(ReturnCode::FAIL),
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm> time::Client for SmartCard<'a, A> {
+    fn fired(&self) {
+        if self.state.get() == State::Reset {
+            self.release_reset();
+        }
+    }
+}
+
+impl<'a, A: Alarm> Driver for SmartCard<'a, A> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Writeable buffer for the ATR and response APDUs
+    /// - `1`: Buffer holding the command APDU
+    fn allow(&self, appid: AppId, allow_num: usize, slice: Option<AppSlice<Shared, u8>>) -> ReturnCode {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.read_buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.write_buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Operation complete, with the status and response length
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.apps.enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    /// Smartcard operations
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Reset the card and read its ATR.
+    /// - `2`: Exchange the APDU of length `arg1` passed through `allow`.
+    /// - `3`: Select protocol T=`arg1` (0 or 1).
+    /// - `4`: Set the extra guard time to `arg1` ETUs. As in TC1, 255 means
+    ///        the minimum guard time.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        if cmd_num == 0 {
+            return ReturnCode::SUCCESS;
+        }
+        if self.state.get() != State::Idle {
+            return ReturnCode::EBUSY;
+        }
+
+        match cmd_num {
+            1 /* reset */ => {
+                self.current_app.set(Some(appid));
+                self.reset();
+                ReturnCode::SUCCESS
+            },
+            2 /* exchange APDU */ => {
+                let len = arg1;
+                let copied = self.apps.enter(appid, |app, _| {
+                    app.write_buffer.as_ref().map_or(ReturnCode::ERESERVE, |src| {
+                        self.apdu.map_or(ReturnCode::ERESERVE, |apdu| {
+                            if len > src.len() || len > apdu.len() - 5 {
+                                return ReturnCode::ESIZE;
+                            }
+                            apdu[..len].copy_from_slice(&src.as_ref()[..len]);
+                            ReturnCode::SUCCESS
+                        })
+                    })
+                }).unwrap_or_else(|err| err.into());
+                if copied != ReturnCode::SUCCESS {
+                    return copied;
+                }
+
+                self.apdu_len.set(len);
+                self.current_app.set(Some(appid));
+                let result = match self.protocol.get() {
+                    Iso7816Protocol::T0 => self.start_t0(len),
+                    Iso7816Protocol::T1 => self.start_t1(len),
+                };
+                if result != ReturnCode::SUCCESS {
+                    self.current_app.set(None);
+                }
+                result
+            },
+            3 /* protocol */ => {
+                match arg1 {
+                    0 => self.set_protocol(Iso7816Protocol::T0),
+                    1 => self.set_protocol(Iso7816Protocol::T1),
+                    _ => return ReturnCode::EINVAL,
+                }
+                ReturnCode::SUCCESS
+            },
+            4 /* guard time */ => {
+                if arg1 > 255 {
+                    return ReturnCode::EINVAL;
+                }
+                self.set_guard_time(arg1 as u8);
+                ReturnCode::SUCCESS
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
+
+impl<'a, A: Alarm> Client for SmartCard<'a, A> {
+    fn transmit_complete(&self, buffer: &'static mut [u8], _error: uart::Error) {
+        self.tx_buffer.replace(buffer);
+        if self.state.get() != State::Idle {
+            self.receive(self.next_rx_len.get());
+        }
+    }
+
+    fn receive_complete(&self, rx_buffer: &'static mut [u8], rx_len: usize, _error: uart::Error) {
+        let mut data = [0; 272];
+        let len = cmp::min(rx_len, data.len());
+        data[..len].copy_from_slice(&rx_buffer[..len]);
+        self.rx_buffer.replace(rx_buffer);
+        let data = &data[..len];
+
+        match self.state.get() {
+            State::Idle | State::Reset => {}
+            State::Failed(result) => self.finish(result),
+            State::Atr => {
+                if !self.append_response(data) {
+                    self.finish(ReturnCode::ESIZE);
+                    return;
+                }
+                let received = self.response_len.get();
+                let expected = self.response.map_or(received, |response| {
+                    cmp::min(atr_length(&response[..received]), ATR_MAX_LEN)
+                });
+                if received < expected {
+                    self.receive(expected - received);
+                } else {
+                    self.atr_complete();
+                }
+            }
+            State::T0Procedure => {
+                if len == 1 {
+                    self.t0_procedure_byte(data[0]);
+                } else {
+                    self.finish(ReturnCode::FAIL);
+                }
+            }
+            State::T0Data => {
+                if self.append_response(data) {
+                    self.state.set(State::T0Procedure);
+                    self.receive(1);
+                } else {
+                    self.finish(ReturnCode::ESIZE);
+                }
+            }
+            State::T0Sw2 => {
+                self.append_response(data);
+                self.finish(ReturnCode::SUCCESS);
+            }
+            State::T1Prologue => {
+                if len == 3 {
+                    self.t1_prologue.set([data[0], data[1], data[2]]);
+                    self.state.set(State::T1Body);
+                    self.receive(data[2] as usize + 1);
+                } else {
+                    self.finish(ReturnCode::FAIL);
+                }
+            }
+            State::T1Body => {
+                let expected = self.t1_prologue.get()[2] as usize + 1;
+                if len == expected {
+                    self.t1_block(data);
+                } else {
+                    self.finish(ReturnCode::FAIL);
+                }
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm> Iso7816Client for SmartCard<'a, A> {
+    fn iso7816_event(&self, event: Iso7816Event) {
+        let failure = match event {
+            Iso7816Event::InitialCharacter { .. } => None,
+            Iso7816Event::GuardTimeViolation => None,
+            Iso7816Event::WaitTimeout => Some(ReturnCode::ENOACK),
+            Iso7816Event::TransmitRetriesExceeded |
+            Iso7816Event::ReceiveRetriesExceeded => Some(ReturnCode::FAIL),
+        };
+
+        // Cut the outstanding receive short; `receive_complete` then reports
+        // the failure to the app.
+        if let Some(result) = failure {
+            if self.state.get() != State::Idle && self.state.get() != State::Reset {
+                self.state.set(State::Failed(result));
+                self.uart.abort_receive();
+            }
+        }
+    }
+}
+
+#[derive(Copy, Clone, Default)]
+struct AtrInfo {
+    guard_time: u8,
+}
+
+/// Returns the total length of the ATR whose first bytes are `atr`, as far as
+/// can be told from the interface bytes received so far.
+fn atr_length(atr: &[u8]) -> usize {
+    if atr.len() < 2 {
+        return 2;
+    }
+
+    let historical = (atr[1] & 0x0F) as usize;
+    let mut indicator = atr[1] >> 4;
+    let mut index = 2;
+    let mut tck = false;
+    loop {
+        let count = indicator.count_ones() as usize;
+        if indicator & 0x8 == 0 {
+            index += count;
+            break;
+        }
+
+        // TDi is the last interface byte of the group and announces the next.
+        let td = index + count - 1;
+        if td >= atr.len() {
+            return td + 1;
+        }
+        if atr[td] & 0x0F != 0 {
+            tck = true;
+        }
+        indicator = atr[td] >> 4;
+        index = td + 1;
+    }
+
+    index + historical + if tck { 1 } else { 0 }
+}
+
+fn parse_atr(atr: &[u8]) -> AtrInfo {
+    let mut info = AtrInfo::default();
+    if atr.len() < 2 {
+        return info;
+    }
+
+    // TC1, the extra guard time, follows TA1 and TB1 when they are present.
+    let t0 = atr[1];
+    if t0 & 0x40 != 0 {
+        let tc1 = 2 + (t0 & 0x30).count_ones() as usize;
+        if tc1 < atr.len() {
+            info.guard_time = atr[tc1];
+        }
+    }
+    info
+}
+
+/// Converts TC1's extra guard time N into the value for the UART. N = 255
+/// asks for the minimum guard time, 12 ETUs for T=0 and 11 for T=1, rather
+/// than 255 extra ETUs. The UART has no 11 ETU setting, so both get 12,
+/// which the card must accept since the guard time is only a minimum.
+fn extra_guard_time(n: u8) -> u8 {
+    if n == 255 { 0 } else { n }
+}
+
+/// Longitudinal redundancy check used as the T=1 epilogue.
+fn lrc(data: &[u8]) -> u8 {
+    data.iter().fold(0, |acc, byte| acc ^ byte)
+}
@@ -0,0 +1,343 @@
+//! Loads processes with per-process memory limits and restarts faulted
+//! processes with exponential backoff.
+//!
+//! The chip tells the supervisor which process faulted, and the supervisor
+//! takes it out of the process table straight away, whatever state the
+//! kernel's fault response left it in. After a backoff that doubles with
+//! each restart, it recreates the process from its flash image in the
+//! memory it had before. A process that keeps faulting is left out; one
+//! that stays up for a while has its backoff reset. The alarm is only armed
+//! while a restart or a backoff reset is due, so an idle board can sleep
+//! deeply.
+//!
+//! The process table is the kernel's: `kernel_loop` keeps it in
+//! `kernel::procs::PROCS`, and the supervisor changes it there, the same way
+//! grants and IPC reach processes. The supervisor only does so from
+//! interrupt and alarm callbacks, which the kernel runs between processes.
+//! Other kernel code, such as the process console, stops and starts
+//! processes through `ProcessControl`.
+//!
+//! A recreated process has the same `AppId` as the one it replaces, but
+//! fresh grants. Capsules that hold on to an `AppId` across callbacks tell
+//! the two apart by keeping some state in the grant as well.
+
+use core::cell::Cell;
+use core::cmp;
+use kernel;
+use kernel::ReturnCode;
+use kernel::common::cells::TakeCell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::procs::{Process, FaultResponse};
+use mk66::chip::ProcessFaultClient;
+
+/// Delay before the first restart; each further restart doubles it.
+const INITIAL_BACKOFF_MS: u32 = 250;
+const MAX_BACKOFF_MS: u32 = 30_000;
+/// Restarts without a healthy period before a process is left stopped.
+const MAX_RESTARTS: usize = 8;
+/// Time a restarted process must run without faulting to reset its backoff.
+const HEALTHY_MS: u32 = 10_000;
+
+/// Where a process was loaded from, and its restart history.
+#[derive(Copy, Clone)]
+pub struct LoadedApp {
+    flash: *const u8,
+    flash_size: usize,
+    memory: *mut u8,
+    memory_size: usize,
+    faulted: bool,
+    restarts: usize,
+    restart_at: Option<u32>,
+    started_at: u32,
+    given_up: bool,
+}
+
+impl LoadedApp {
+    pub const fn empty() -> LoadedApp {
+        LoadedApp {
+            flash: 0 as *const u8,
+            flash_size: 0,
+            memory: 0 as *mut u8,
+            memory_size: 0,
+            faulted: false,
+            restarts: 0,
+            restart_at: None,
+            started_at: 0,
+            given_up: false,
+        }
+    }
+
+    /// Whether `address` is in the app's flash image or its memory.
+    fn holds(&self, address: u32) -> bool {
+        let address = address as usize;
+        let flash = self.flash as usize;
+        let memory = self.memory as usize;
+        (address >= flash && address < flash + self.flash_size) ||
+        (address >= memory && address < memory + self.memory_size)
+    }
+}
+
+/// Loads the apps starting at `start_of_flash` into `app_memory`. Process
+/// `i` may use at most `memory_limits[i]` bytes, so one large app cannot
+/// starve the rest; the table is shorter than `procs` to leave later
+/// processes unlimited.
+pub unsafe fn load_processes(start_of_flash: *const u8,
+                             app_memory: &mut [u8],
+                             memory_limits: &[usize],
+                             procs: &mut [Option<&'static mut Process<'static>>],
+                             apps: &mut [LoadedApp],
+                             fault_response: FaultResponse) {
+    let mut flash = start_of_flash;
+    let mut memory = app_memory.as_mut_ptr();
+    let mut memory_left = app_memory.len();
+
+    for i in 0..procs.len() {
+        let limit = memory_limits.get(i).map_or(memory_left, |limit| cmp::min(*limit, memory_left));
+        let (process, flash_used, memory_used) =
+            Process::create(flash, memory, limit, fault_response);
+
+        if process.is_none() && flash_used == 0 && memory_used == 0 {
+            // No more apps in flash.
+            break;
+        }
+        if process.is_some() {
+            apps[i] = LoadedApp {
+                flash: flash,
+                flash_size: flash_used,
+                memory: memory,
+                memory_size: limit,
+                ..LoadedApp::empty()
+            };
+        }
+        procs[i] = process;
+
+        flash = flash.offset(flash_used as isize);
+        memory = memory.offset(memory_used as isize);
+        memory_left -= memory_used;
+    }
+}
+
+/// Calls `f` with slot `index` of the kernel's process table. Before
+/// `kernel_loop` starts the table is empty, and this returns None.
+fn with_slot<F, R>(index: usize, f: F) -> Option<R>
+    where F: FnOnce(&mut Option<&'static mut Process<'static>>) -> R
+{
+    unsafe { kernel::procs::PROCS.get_mut(index).map(f) }
+}
+
+/// Stops, starts and inspects processes for other kernel code.
+pub trait ProcessControl {
+    /// Number of process slots.
+    fn len(&self) -> usize;
+
+    /// Calls `f` with process `index` and whether it is stopped. Returns
+    /// false if there is no such process.
+    fn inspect(&self, index: usize, f: &mut FnMut(&mut Process<'static>, bool)) -> bool;
+
+    /// Takes a process out of the table, so it is no longer scheduled and
+    /// callbacks for it are dropped. Fails with `EINVAL` if it isn't running.
+    fn stop(&self, index: usize) -> ReturnCode;
+
+    /// Puts a stopped process back where it left off. Fails with `EINVAL` if
+    /// it isn't stopped.
+    fn start(&self, index: usize) -> ReturnCode;
+}
+
+pub struct ProcessSupervisor<'a, A: Alarm + 'a> {
+    alarm: &'a A,
+    stopped: TakeCell<'static, [Option<&'static mut Process<'static>>]>,
+    apps: TakeCell<'static, [LoadedApp]>,
+    fault_response: FaultResponse,
+    running: Cell<bool>,
+}
+
+impl<'a, A: Alarm> ProcessSupervisor<'a, A> {
+    /// `apps` describes the processes loaded into the table handed to
+    /// `kernel_loop`. `stopped` holds processes taken out of the table, and
+    /// must be as long as `apps`.
+    pub fn new(alarm: &'a A,
+               stopped: &'static mut [Option<&'static mut Process<'static>>],
+               apps: &'static mut [LoadedApp],
+               fault_response: FaultResponse)
+               -> ProcessSupervisor<'a, A> {
+        ProcessSupervisor {
+            alarm: alarm,
+            stopped: TakeCell::new(stopped),
+            apps: TakeCell::new(apps),
+            fault_response: fault_response,
+            running: Cell::new(false),
+        }
+    }
+
+    pub fn start(&self) {
+        let now = self.alarm.now();
+        self.apps.map(|apps| {
+            for app in apps.iter_mut() {
+                app.started_at = now;
+            }
+        });
+        self.running.set(true);
+        self.check_all();
+    }
+
+    fn ticks(ms: u32) -> u32 {
+        A::Frequency::frequency() / 1000 * ms
+    }
+
+    fn due(when: u32, now: u32) -> bool {
+        (now.wrapping_sub(when) as i32) >= 0
+    }
+
+    /// Checks every process, then arms the alarm for the earliest restart or
+    /// backoff reset, or leaves it off if none is due.
+    fn check_all(&self) {
+        if !self.running.get() {
+            return;
+        }
+
+        let now = self.alarm.now();
+        let mut next: Option<u32> = None;
+        self.apps.map(|apps| {
+            for (i, app) in apps.iter_mut().enumerate() {
+                if let Some(when) = self.check(i, app, now) {
+                    next = match next {
+                        Some(earliest) if earliest.wrapping_sub(now) <= when.wrapping_sub(now) => Some(earliest),
+                        _ => Some(when),
+                    };
+                }
+            }
+        });
+
+        match next {
+            Some(when) => self.alarm.set_alarm(when),
+            None => self.alarm.disable(),
+        }
+    }
+
+    /// Restarts or gives up on a faulted process. Returns when the process
+    /// next needs checking, if a restart or backoff reset is pending.
+    fn check(&self, index: usize, app: &mut LoadedApp, now: u32) -> Option<u32> {
+        if app.flash.is_null() || app.given_up {
+            return None;
+        }
+
+        if !app.faulted {
+            if app.restarts == 0 {
+                return None;
+            }
+            let healthy_at = app.started_at.wrapping_add(Self::ticks(HEALTHY_MS));
+            if Self::due(healthy_at, now) {
+                app.restarts = 0;
+                return None;
+            }
+            return Some(healthy_at);
+        }
+
+        match app.restart_at {
+            None => {
+                if app.restarts >= MAX_RESTARTS {
+                    app.given_up = true;
+                    debug!("Process {} faulted {} times, leaving it stopped", index, app.restarts + 1);
+                    return None;
+                }
+                let backoff = cmp::min(INITIAL_BACKOFF_MS << app.restarts, MAX_BACKOFF_MS);
+                let when = now.wrapping_add(Self::ticks(backoff));
+                app.restart_at = Some(when);
+                debug!("Process {} faulted, restarting in {} ms", index, backoff);
+                Some(when)
+            }
+            Some(when) => {
+                if !Self::due(when, now) {
+                    return Some(when);
+                }
+                // The faulted process left the table when the fault was
+                // reported, so nothing refers to its memory any more.
+                let (process, _, _) = unsafe {
+                    Process::create(app.flash, app.memory, app.memory_size, self.fault_response)
+                };
+                if process.is_none() {
+                    app.given_up = true;
+                    debug!("Process {} could not be recreated", index);
+                    return None;
+                }
+                with_slot(index, |slot| *slot = process);
+                app.faulted = false;
+                app.restarts += 1;
+                app.restart_at = None;
+                app.started_at = now;
+                Some(now.wrapping_add(Self::ticks(HEALTHY_MS)))
+            }
+        }
+    }
+}
+
+impl<'a, A: Alarm> time::Client for ProcessSupervisor<'a, A> {
+    fn fired(&self) {
+        self.check_all();
+    }
+}
+
+impl<'a, A: Alarm> ProcessFaultClient for ProcessSupervisor<'a, A> {
+    fn process_faulted(&self, region: u32) {
+        self.apps.map(|apps| {
+            apps.iter_mut().enumerate()
+                .find(|&(_, ref app)| !app.flash.is_null() && app.holds(region))
+                .map(|(i, app)| {
+                    // Dropping the process here, rather than leaving it to the
+                    // kernel's fault response, keeps it from running again
+                    // before it is recreated.
+                    app.faulted = true;
+                    with_slot(i, |slot| slot.take());
+                });
+        });
+        self.check_all();
+    }
+}
+
+impl<'a, A: Alarm> ProcessControl for ProcessSupervisor<'a, A> {
+    fn len(&self) -> usize {
+        self.apps.map_or(0, |apps| apps.len())
+    }
+
+    fn inspect(&self, index: usize, f: &mut FnMut(&mut Process<'static>, bool)) -> bool {
+        let running = with_slot(index, |slot| {
+            slot.as_mut().map(|process| f(process, false)).is_some()
+        });
+        if running == Some(true) {
+            return true;
+        }
+        self.stopped.map_or(false, |stopped| {
+            match stopped.get_mut(index) {
+                Some(&mut Some(ref mut process)) => {
+                    f(process, true);
+                    true
+                }
+                _ => false,
+            }
+        })
+    }
+
+    fn stop(&self, index: usize) -> ReturnCode {
+        self.stopped.map_or(ReturnCode::FAIL, |stopped| {
+            match with_slot(index, |slot| slot.take()) {
+                Some(Some(process)) => {
+                    stopped[index] = Some(process);
+                    ReturnCode::SUCCESS
+                }
+                _ => ReturnCode::EINVAL,
+            }
+        })
+    }
+
+    fn start(&self, index: usize) -> ReturnCode {
+        self.stopped.map_or(ReturnCode::FAIL, |stopped| {
+            match stopped.get_mut(index).and_then(|slot| slot.take()) {
+                Some(process) => {
+                    with_slot(index, |slot| *slot = Some(process));
+                    ReturnCode::SUCCESS
+                }
+                None => ReturnCode::EINVAL,
+            }
+        })
+    }
+}
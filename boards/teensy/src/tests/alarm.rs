@@ -4,7 +4,7 @@ use mk66::{gpio, clock, pit};
 use kernel::hil::time::{Time, Alarm, Frequency, Client};
 use tests::blink;
 
-static mut INTERVAL: u32 = 18_000_000;
+static mut INTERVAL: u32 = 300_000;
 static mut UP: bool = false;
 
 static mut LAST_TIME: u32 = 0;
@@ -17,10 +17,10 @@ impl Client for LedClient {
             let gap = now - LAST_TIME;
             let wasted = gap - INTERVAL;
             blink::led_toggle();
-            if INTERVAL > 18_000_000 || INTERVAL < 4_000_000 {
+            if INTERVAL > 300_000 || INTERVAL < 66_000 {
                 UP = !UP;
             }
-            INTERVAL = if UP {INTERVAL + 1_000_000} else { INTERVAL - 1_000_000 };
+            INTERVAL = if UP {INTERVAL + 16_000} else { INTERVAL - 16_000 };
             LAST_TIME = now;
             pit::PIT.set_alarm(INTERVAL);
             println!("Interval: {}, Time: {}, Gap: {}, Overhead: {}", INTERVAL, now, gap, wasted);
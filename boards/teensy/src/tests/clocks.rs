@@ -0,0 +1,93 @@
+use mk66::clock::{self, ClockConfig, Reference, Source};
+use mk66::sim::ClkOut;
+use pins;
+use tests::alarm;
+use mk66::mcg::xtals;
+
+fn show(name: &str, config: ClockConfig) {
+    match clock::solve(&config) {
+        Ok(tree) => println!("{}: {:?} -> {:?}", name, tree, tree.clocks()),
+        Err(err) => println!("{}: {:?}", name, err),
+    }
+}
+
+pub fn clock_test() {
+    show("180 MHz PLL", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 180_000_000,
+        bus_hz: 60_000_000,
+        flexbus_hz: 60_000_000,
+        flash_hz: 28_000_000,
+    });
+
+    // The PLL can't run below 90 MHz, so the core is divided down.
+    show("48 MHz PLL", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 48_000_000,
+        bus_hz: 48_000_000,
+        flexbus_hz: 24_000_000,
+        flash_hz: 24_000_000,
+    });
+
+    show("20 MHz FLL", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Fll,
+        core_hz: 20_000_000,
+        bus_hz: 20_000_000,
+        flexbus_hz: 20_000_000,
+        flash_hz: 10_000_000,
+    });
+
+    // Each of these should be rejected.
+    show("Core too fast", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 200_000_000,
+        bus_hz: 50_000_000,
+        flexbus_hz: 50_000_000,
+        flash_hz: 25_000_000,
+    });
+
+    show("Flash too fast", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 120_000_000,
+        bus_hz: 60_000_000,
+        flexbus_hz: 50_000_000,
+        flash_hz: 40_000_000,
+    });
+
+    show("No PLL setting", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 97_000_000,
+        bus_hz: 48_000_000,
+        flexbus_hz: 48_000_000,
+        flash_hz: 24_000_000,
+    });
+
+    show("Bus too slow", ClockConfig {
+        xtal: xtals::Teensy16MHz,
+        source: Source::Pll,
+        core_hz: 120_000_000,
+        bus_hz: 1_000_000,
+        flexbus_hz: 1_000_000,
+        flash_hz: 1_000_000,
+    });
+}
+
+/// Puts the flash clock on pin 9 and repeatedly measures the core clock
+/// against the RTC crystal, which takes a second to start the first time.
+pub fn clock_measure_test() {
+    unsafe { pins::configure_clkout_pin(); }
+    clock::route_clkout(ClkOut::Flash);
+
+    alarm::loop_500ms(|| {
+        println!("Core clock: {} Hz, measured {} Hz against the RTC, {} Hz against the LPO",
+                 clock::core_clock_hz(),
+                 clock::measure_core_hz(Reference::Rtc, 3277),
+                 clock::measure_core_hz(Reference::Lpo, 100));
+    });
+}
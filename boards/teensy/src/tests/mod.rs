@@ -17,6 +17,12 @@ mod spi;
 #[allow(dead_code)]
 mod rng;
 
+#[allow(dead_code)]
+mod uart;
+
+#[allow(dead_code)]
+mod clocks;
+
 // Set this function to run whatever test you desire. Test functions are named XXX_test by convention.
 pub fn test() {
     spi::spi_test();
@@ -0,0 +1,52 @@
+use mk66::{clock, mcg, uart};
+use io;
+
+const BAUD_RATES: [u32; 10] = [9600, 19200, 38400, 57600, 115200,
+                               230400, 250000, 460800, 921600, 1_000_000];
+
+/// Runs the baud rate divisor math against every core frequency
+/// `clock::solve` accepts for the Teensy crystal, with the bus, FlexBus and
+/// flash as fast as they go, and reports any rate that falls outside
+/// tolerance.
+pub fn baud_test() {
+    let mut failures = 0;
+
+    for core_mhz in 1..181 {
+        let config = clock::ClockConfig {
+            xtal: mcg::xtals::Teensy16MHz,
+            source: clock::Source::Pll,
+            core_hz: core_mhz * 1_000_000,
+            bus_hz: 60_000_000,
+            flexbus_hz: 50_000_000,
+            flash_hz: 28_000_000,
+        };
+        let clocks = match clock::solve(&config) {
+            Ok(tree) => tree.clocks(),
+            Err(_) => continue,
+        };
+        let (core_hz, bus_hz) = (clocks.core, clocks.bus);
+
+        for &(name, clock_hz) in [("core", core_hz), ("bus", bus_hz)].iter() {
+            for &baud in BAUD_RATES.iter() {
+                match uart::baud_divisor(clock_hz, baud) {
+                    Ok(div) => {
+                        let ppm = (div.baud_rate as i64 - baud as i64) * 1_000_000 / baud as i64;
+                        println!("{} MHz {} clock, {} baud: SBR {} BRFA {} -> {} ({} ppm)",
+                                 core_mhz, name, baud, div.sbr, div.brfa, div.baud_rate, ppm);
+                    }
+                    Err(_) => {
+                        failures += 1;
+                        println!("{} MHz {} clock, {} baud: out of tolerance",
+                                 core_mhz, name, baud);
+                    }
+                }
+            }
+            // This runs before the kernel loop, so nothing drains the debug
+            // buffer for us.
+            unsafe { io::debug_flush_sync(); }
+        }
+    }
+
+    println!("Baud rate test finished: {} configurations out of tolerance", failures);
+    unsafe { io::debug_flush_sync(); }
+}
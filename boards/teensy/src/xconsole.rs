@@ -14,6 +14,7 @@
 //!                  115200,
 //!                  &mut xconsole::WRITE_BUF,
 //!                  &mut xconsole::READ_BUF,
+//!                  &mut xconsole::ECHO_BUF,
 //!                  kernel::Grant::create()));
 //! hil::uart::UART::set_client(&usart::USART0, console);
 //! ```
@@ -21,8 +22,6 @@
 //! Usage
 //! -----
 //!
-//! Currently, only writing buffers to the serial device is implemented.
-//!
 //! The user must perform three steps in order to write a buffer:
 //!
 //! ```c
@@ -37,6 +36,20 @@
 //! When the buffer has been written successfully, the buffer is released from
 //! the driver. Successive writes must call `allow` each time a buffer is to be
 //! written.
+//!
+//! Any number of apps may read at the same time. Each app chooses between raw
+//! reads, which complete once the requested number of bytes has arrived, and
+//! line reads, which edit the input (backspace) and complete at a newline.
+//! Either mode can echo input back. Input goes to every app with a read
+//! outstanding, unless one app has claimed the input exclusively (e.g. for
+//! the duration of a file transfer).
+//!
+//! ```c
+//! allow(CONSOLE_DRIVER_NUM, 0, line, sizeof(line));
+//! subscribe(CONSOLE_DRIVER_NUM, 0, line_callback, NULL);
+//! command(CONSOLE_DRIVER_NUM, 3, 1 /* line */, 1 /* echo */);
+//! command(CONSOLE_DRIVER_NUM, 2, sizeof(line), 0);
+//! ```
 
 use core::cell::Cell;
 use core::cmp;
@@ -46,6 +59,28 @@ use kernel::hil::uart::{self, UART, Client};
 
 pub const DRIVER_NUM: usize = 0x00000001;
 
+/// A source of kernel output, such as debug messages, sent whenever the
+/// console has no echo to send.
+pub trait KernelOutput {
+    /// Copies out as many pending bytes as fit, returning how many.
+    fn fill(&self, buffer: &mut [u8]) -> usize;
+}
+
+/// A kernel consumer that can take over console input, such as a file
+/// transfer.
+pub trait InputClient {
+    /// Returns false to pass the byte on to apps.
+    fn received(&self, byte: u8) -> bool;
+}
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum ReadMode {
+    /// Reads complete once the requested number of bytes has arrived.
+    Raw,
+    /// Reads complete at a newline, with backspace editing.
+    Line,
+}
+
 pub struct App {
     write_callback: Option<Callback>,
     read_callback: Option<Callback>,
@@ -55,6 +90,14 @@ pub struct App {
     write_remaining: usize, // How many bytes didn't fit in the buffer and still need to be printed.
     pending_write: bool,
     read_idx: usize,
+    read_len: usize,
+    read_pending: bool,
+    read_mode: ReadMode,
+    echo: bool,
+    last_cr: bool,
+    /// Set while this app holds exclusive input. Restarting the app clears
+    /// its grant, and with it the claim.
+    exclusive: bool,
 }
 
 impl Default for App {
@@ -68,12 +111,19 @@ impl Default for App {
             write_remaining: 0,
             pending_write: false,
             read_idx: 0,
+            read_len: 0,
+            read_pending: false,
+            read_mode: ReadMode::Raw,
+            echo: false,
+            last_cr: false,
+            exclusive: false,
         }
     }
 }
 
 pub static mut WRITE_BUF: [u8; 64] = [0; 64];
 pub static mut READ_BUF: [u8; 80] = [0; 80];
+pub static mut ECHO_BUF: [u8; 32] = [0; 32];
 
 pub struct XConsole<'a, U: UART + 'a> {
     uart: &'a U,
@@ -81,7 +131,13 @@ pub struct XConsole<'a, U: UART + 'a> {
     in_progress_tx: Cell<Option<AppId>>,
     tx_buffer: TakeCell<'static, [u8]>,
     rx_buffer: TakeCell<'static, [u8]>,
-    in_progress_rx: Cell<Option<AppId>>,
+    rx_exclusive: Cell<Option<AppId>>,
+    rx_dropped: Cell<usize>,
+    echo_buffer: TakeCell<'static, [u8]>,
+    echo_len: Cell<usize>,
+    kernel_tx: Cell<bool>,
+    input_client: Cell<Option<&'a InputClient>>,
+    kernel_output: Cell<Option<&'a KernelOutput>>,
     baud_rate: u32,
 }
 
@@ -90,6 +146,7 @@ impl<'a, U: UART> XConsole<'a, U> {
                baud_rate: u32,
                tx_buffer: &'static mut [u8],
                rx_buffer: &'static mut [u8],
+               echo_buffer: &'static mut [u8],
                container: Grant<App>)
                -> XConsole<'a, U> {
         XConsole {
@@ -99,7 +156,13 @@ impl<'a, U: UART> XConsole<'a, U> {
             tx_buffer: TakeCell::new(tx_buffer),
             rx_buffer: TakeCell::new(rx_buffer),
             baud_rate: baud_rate,
-            in_progress_rx: Cell::new(None),
+            rx_exclusive: Cell::new(None),
+            rx_dropped: Cell::new(0),
+            echo_buffer: TakeCell::new(echo_buffer),
+            echo_len: Cell::new(0),
+            kernel_tx: Cell::new(false),
+            input_client: Cell::new(None),
+            kernel_output: Cell::new(None),
         }
     }
 
@@ -110,6 +173,204 @@ impl<'a, U: UART> XConsole<'a, U> {
             parity: uart::Parity::None,
             hw_flow_control: false,
         });
+
+        // Input is received one byte at a time for as long as the console
+        // runs, and handed to whichever apps are reading.
+        self.rx_buffer.take().map(|buffer| {
+            self.uart.receive(buffer, 1);
+        });
+    }
+
+    /// Attaches a kernel consumer that sees console input before apps do.
+    pub fn set_input_client(&self, client: &'a InputClient) {
+        self.input_client.set(Some(client));
+    }
+
+    /// Attaches a source of kernel output, which takes priority over app
+    /// writes.
+    pub fn set_kernel_output(&self, output: &'a KernelOutput) {
+        self.kernel_output.set(Some(output));
+    }
+
+    /// Tells the console that kernel output is pending.
+    pub fn kernel_output_ready(&self) {
+        self.send_kernel_output();
+    }
+
+    /// Sends a few bytes from the kernel, ahead of any pending app writes.
+    /// Bytes that do not fit in the echo buffer are dropped.
+    pub fn write_bytes(&self, bytes: &[u8]) {
+        self.echo(bytes);
+    }
+
+    /// Number of input bytes discarded because no app was reading.
+    pub fn rx_dropped(&self) -> usize {
+        self.rx_dropped.get()
+    }
+
+    /// Internal helper function for starting a read of up to `len` bytes.
+    fn read_new(&self, app: &mut App, len: usize) -> ReturnCode {
+        if app.read_pending {
+            return ReturnCode::EBUSY;
+        }
+        match app.read_buffer {
+            Some(ref slice) => {
+                app.read_len = cmp::min(len, slice.len());
+                app.read_idx = 0;
+                app.read_pending = app.read_len > 0;
+                ReturnCode::SUCCESS
+            }
+            None => ReturnCode::ERESERVE,
+        }
+    }
+
+    /// The app holding exclusive input. A claim lapses once its owner has
+    /// exited or been restarted.
+    fn exclusive_owner(&self) -> Option<AppId> {
+        self.rx_exclusive.get().and_then(|owner| {
+            let alive = self.apps.enter(owner, |app, _| app.exclusive).unwrap_or(false);
+            if alive {
+                Some(owner)
+            } else {
+                self.rx_exclusive.set(None);
+                None
+            }
+        })
+    }
+
+    /// Hands a received byte to every reading app (or only the app holding
+    /// exclusive input) and echoes it once if any of them asked for echo.
+    fn receive_byte(&self, byte: u8) {
+        let exclusive = self.exclusive_owner();
+        let mut delivered = false;
+        let mut echo = [0; 3];
+        let mut echo_len = 0;
+
+        for cntr in self.apps.iter() {
+            cntr.enter(|app, _| {
+                if !app.read_pending {
+                    return;
+                }
+                if exclusive.map_or(false, |owner| owner != app.appid()) {
+                    return;
+                }
+                delivered = true;
+
+                let (out, len) = match app.read_mode {
+                    ReadMode::Raw => self.receive_raw(app, byte),
+                    ReadMode::Line => self.receive_line(app, byte),
+                };
+                if app.echo && echo_len == 0 {
+                    echo = out;
+                    echo_len = len;
+                }
+            });
+        }
+
+        if !delivered {
+            self.rx_dropped.set(self.rx_dropped.get() + 1);
+        }
+        if echo_len > 0 {
+            self.echo(&echo[..echo_len]);
+        }
+    }
+
+    /// Stores a byte for a raw read, returning what to echo.
+    fn receive_raw(&self, app: &mut App, byte: u8) -> ([u8; 3], usize) {
+        self.store(app, byte);
+        if app.read_idx >= app.read_len {
+            self.read_done(app);
+        }
+        ([byte, 0, 0], 1)
+    }
+
+    /// Applies line editing to a byte for a line read, returning what to
+    /// echo.
+    fn receive_line(&self, app: &mut App, byte: u8) -> ([u8; 3], usize) {
+        let after_cr = app.last_cr;
+        app.last_cr = byte == b'\r';
+
+        match byte {
+            // Treat CR LF as a single newline.
+            b'\n' if after_cr => ([0; 3], 0),
+            b'\r' | b'\n' => {
+                self.read_done(app);
+                ([b'\r', b'\n', 0], 2)
+            }
+            0x08 | 0x7F => {
+                if app.read_idx > 0 {
+                    app.read_idx -= 1;
+                    ([0x08, b' ', 0x08], 3)
+                } else {
+                    ([0; 3], 0)
+                }
+            }
+            _ => {
+                self.store(app, byte);
+                if app.read_idx >= app.read_len {
+                    self.read_done(app);
+                }
+                ([byte, 0, 0], 1)
+            }
+        }
+    }
+
+    fn store(&self, app: &mut App, byte: u8) {
+        let idx = app.read_idx;
+        if idx < app.read_len {
+            app.read_buffer.as_mut().map(|buffer| {
+                buffer.as_mut()[idx] = byte;
+            });
+            app.read_idx += 1;
+        }
+    }
+
+    fn read_done(&self, app: &mut App) {
+        let len = app.read_idx;
+        app.read_pending = false;
+        app.read_idx = 0;
+        app.read_callback.map(|mut cb| { cb.schedule(len, 0, 0); });
+    }
+
+    /// Queues bytes to be echoed, sending them now if the UART is idle.
+    fn echo(&self, bytes: &[u8]) {
+        let start = self.echo_len.get();
+        self.echo_buffer.map(|buffer| {
+            let len = cmp::min(bytes.len(), buffer.len() - start);
+            buffer[start..start + len].copy_from_slice(&bytes[..len]);
+            self.echo_len.set(start + len);
+        });
+        self.send_kernel_output();
+    }
+
+    /// Sends queued echo bytes, or else pending kernel output, if no
+    /// transmission is in progress. Returns true if a transmission was
+    /// started.
+    fn send_kernel_output(&self) -> bool {
+        if self.in_progress_tx.get().is_some() || self.kernel_tx.get() {
+            return false;
+        }
+
+        self.tx_buffer.take().map_or(false, |buffer| {
+            let echo_len = self.echo_len.get();
+            let len = if echo_len > 0 {
+                self.echo_buffer.map(|echo| {
+                    buffer[..echo_len].copy_from_slice(&echo[..echo_len]);
+                });
+                self.echo_len.set(0);
+                echo_len
+            } else {
+                self.kernel_output.get().map_or(0, |output| output.fill(buffer))
+            };
+
+            if len == 0 {
+                self.tx_buffer.replace(buffer);
+                return false;
+            }
+            self.kernel_tx.set(true);
+            self.uart.transmit(buffer, len);
+            true
+        })
     }
 
     /// Internal helper function for setting up a new send transaction
@@ -118,6 +379,11 @@ impl<'a, U: UART> XConsole<'a, U> {
             Some(slice) => {
                 app.write_len = cmp::min(len, slice.len());
                 app.write_remaining = app.write_len;
+                if app.write_len == 0 {
+                    app.write_buffer = Some(slice);
+                    app.write_callback.map(|mut cb| { cb.schedule(0, 0, 0); });
+                    return ReturnCode::SUCCESS;
+                }
                 self.send(app_id, app, slice);
                 ReturnCode::SUCCESS
             }
@@ -141,7 +407,7 @@ impl<'a, U: UART> XConsole<'a, U> {
     /// Internal helper function for sending data for an existing transaction.
     /// Cannot fail. If can't send now, it will schedule for sending later.
     fn send(&self, app_id: AppId, app: &mut App, slice: AppSlice<Shared, u8>) {
-        if self.in_progress_tx.get().is_none() {
+        if self.in_progress_tx.get().is_none() && !self.kernel_tx.get() {
             self.in_progress_tx.set(Some(app_id));
             self.tx_buffer.take().map(|buffer| {
                 let mut transaction_len = app.write_remaining;
@@ -187,6 +453,7 @@ impl<'a, U: UART> Driver for XConsole<'a, U> {
                     .enter(appid, |app, _| {
                         app.read_buffer = slice;
                         app.read_idx = 0;
+                        app.read_pending = false;
                         ReturnCode::SUCCESS
                     })
                     .unwrap_or_else(|err| err.into())
@@ -234,7 +501,14 @@ impl<'a, U: UART> Driver for XConsole<'a, U> {
     /// - `0`: Driver check.
     /// - `1`: Prints a buffer passed through `allow` up to the length passed in
     ///        `arg1`
-    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+    /// - `2`: Reads into the buffer passed through `allow`, up to `arg1`
+    ///        bytes, according to the app's read mode
+    /// - `3`: Sets the read mode: `arg1` is 0 for raw or 1 for line reads,
+    ///        `arg2` is 1 to echo input
+    /// - `4`: Aborts an outstanding read, reporting what was read so far
+    /// - `5`: Claims (`arg1` = 1) or releases (`arg1` = 0) exclusive input
+    ///        until the app exits or restarts
+    fn command(&self, cmd_num: usize, arg1: usize, arg2: usize, appid: AppId) -> ReturnCode {
         match cmd_num {
             0 /* check if present */ => ReturnCode::SUCCESS,
             1 /* putstr */ => {
@@ -243,16 +517,55 @@ impl<'a, U: UART> Driver for XConsole<'a, U> {
                     self.send_new(appid, app, len)
                 }).unwrap_or_else(|err| err.into())
             },
-            2 /* raw read */ => {
+            2 /* read */ => {
                 let len = arg1;
-                self.apps.enter(appid, |_app, _| {
-                    self.rx_buffer.take().map_or(ReturnCode::ERESERVE, |buffer| {
-                        self.uart.receive(buffer, len);
-                        self.in_progress_rx.set(Some(appid));
-                        ReturnCode::SUCCESS
-                    })
+                self.apps.enter(appid, |app, _| {
+                    self.read_new(app, len)
                 }).unwrap_or_else(|err| err.into())
             },
+            3 /* read mode */ => {
+                let mode = match arg1 {
+                    0 => ReadMode::Raw,
+                    1 => ReadMode::Line,
+                    _ => return ReturnCode::EINVAL,
+                };
+                self.apps.enter(appid, |app, _| {
+                    if app.read_pending {
+                        return ReturnCode::EBUSY;
+                    }
+                    app.read_mode = mode;
+                    app.echo = arg2 != 0;
+                    app.last_cr = false;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            4 /* abort read */ => {
+                self.apps.enter(appid, |app, _| {
+                    if app.read_pending {
+                        self.read_done(app);
+                    }
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            5 /* exclusive input */ => {
+                match (arg1, self.exclusive_owner()) {
+                    (1, None) => {
+                        self.apps.enter(appid, |app, _| {
+                            app.exclusive = true;
+                            self.rx_exclusive.set(Some(appid));
+                            ReturnCode::SUCCESS
+                        }).unwrap_or_else(|err| err.into())
+                    }
+                    (1, Some(owner)) if owner == appid => ReturnCode::SUCCESS,
+                    (1, Some(_)) => ReturnCode::EBUSY,
+                    (0, Some(owner)) if owner == appid => {
+                        let _ = self.apps.enter(appid, |app, _| app.exclusive = false);
+                        self.rx_exclusive.set(None);
+                        ReturnCode::SUCCESS
+                    }
+                    _ => ReturnCode::EINVAL,
+                }
+            },
             _ => ReturnCode::ENOSUPPORT
         }
     }
@@ -263,9 +576,16 @@ impl<'a, U: UART> Client for XConsole<'a, U> {
         // Either print more from the AppSlice or send a callback to the
         // application.
         self.tx_buffer.replace(buffer);
+
+        self.kernel_tx.set(false);
         self.in_progress_tx.get().map(|appid| {
             self.in_progress_tx.set(None);
             self.apps.enter(appid, |app, _| {
+                // A process recreated in the same slot has the same AppId,
+                // but nothing to write yet.
+                if app.write_len == 0 {
+                    return;
+                }
                 match self.send_continue(appid, app) {
                     Ok(more_to_send) => {
                         if !more_to_send {
@@ -287,9 +607,10 @@ impl<'a, U: UART> Client for XConsole<'a, U> {
             })
         });
 
-        // If we are not printing more from the current AppSlice,
-        // see if any other applications have pending messages.
-        if self.in_progress_tx.get().is_none() {
+        // If we are not printing more from the current AppSlice, send any
+        // echo or kernel output that arrived meanwhile, then see if any
+        // other applications have pending messages.
+        if self.in_progress_tx.get().is_none() && !self.send_kernel_output() {
             for cntr in self.apps.iter() {
                 let started_tx = cntr.enter(|app, _| {
                     if app.pending_write {
@@ -317,28 +638,20 @@ impl<'a, U: UART> Client for XConsole<'a, U> {
         }
     }
 
-    // We don't use the return value of apps.enter since we should
-    // just fail silently and return to an idle state if the app has
-    // died.
-    #[allow(unused)]
     fn receive_complete(&self,
-                        _rx_buffer: &'static mut [u8],
-                        _rx_len: usize,
+                        rx_buffer: &'static mut [u8],
+                        rx_len: usize,
                         _error: uart::Error) {
-        self.in_progress_rx.get().map(|appid| {
-            self.in_progress_rx.set(None);
-            self.apps.enter(appid, |app, _| {
-                {
-                    let dest = app.read_buffer.as_mut().unwrap();
-                    let d = &mut dest.as_mut();
-                    for (i, c) in _rx_buffer[0.._rx_len].iter().enumerate() {
-                        d[i] = *c;
-                    }
-                }
-                app.read_callback.map(|mut cb| {cb.schedule(_rx_len, 0, 0); });
-            });
-        });
-        self.rx_buffer.replace(_rx_buffer);
+        let byte = rx_buffer[0];
+        self.uart.receive(rx_buffer, 1);
 
+        if rx_len > 0 {
+            let consumed = self.input_client.get().map_or(false, |client| {
+                client.received(byte)
+            });
+            if !consumed {
+                self.receive_byte(byte);
+            }
+        }
     }
 }
@@ -0,0 +1,581 @@
+//! Receives files over the console with XMODEM or YMODEM.
+//!
+//! The protocol runs in the kernel, so the transfer does not depend on the
+//! app being scheduled for every byte. While a transfer is running the
+//! capsule consumes all XConsole input, and acknowledgements go out through
+//! XConsole ahead of app writes. Kernel debug output during a transfer will
+//! corrupt it.
+//!
+//! XMODEM transfers use CRC-16 and accept both 128 and 1024 byte blocks. If
+//! the sender does not answer the CRC request a few times the receiver falls
+//! back to the original additive checksum. Verified blocks are copied into
+//! the app's buffer as they arrive, and the app gets one callback when the
+//! transfer ends.
+//!
+//! YMODEM batches deliver one file at a time. After each file the app gets a
+//! callback with the file length (truncated to the size in the file header)
+//! and the header itself in the optional header buffer. The next file starts
+//! once the app issues the continue command, which it can do after reading
+//! out or replacing the buffer. A batch the app doesn't continue within ten
+//! seconds is cancelled.
+//!
+//! If the app exits or is restarted, its transfer is cancelled and console
+//! input goes back to the apps.
+//!
+//! Usage
+//! -----
+//!
+//! ```c
+//! allow(XMODEM_DRIVER_NUM, 0, image, sizeof(image));
+//! subscribe(XMODEM_DRIVER_NUM, 0, callback, NULL);  // (status, len, more)
+//! command(XMODEM_DRIVER_NUM, 1, 0 /* XMODEM */, 0);
+//! ```
+
+use core::cell::Cell;
+use core::cmp;
+use kernel::{AppId, AppSlice, Grant, Callback, Shared, Driver, ReturnCode};
+use kernel::common::cells::TakeCell;
+use kernel::hil::time::{self, Alarm, Frequency};
+use kernel::hil::uart::UART;
+use xconsole::{XConsole, InputClient};
+
+pub const DRIVER_NUM: usize = 0x00090003;
+
+/// Room for a block number, its complement, 1024 bytes of data and a CRC.
+pub static mut BLOCK_BUF: [u8; 1028] = [0; 1028];
+
+const SOH: u8 = 0x01;
+const STX: u8 = 0x02;
+const EOT: u8 = 0x04;
+const ACK: u8 = 0x06;
+const NAK: u8 = 0x15;
+const CAN: u8 = 0x18;
+const CRC_REQUEST: u8 = b'C';
+
+/// Time between polls for the sender, and to wait for the next block.
+const POLL_TIMEOUT_MS: u32 = 3000;
+/// Time to wait for the next byte within a block.
+const BYTE_TIMEOUT_MS: u32 = 1000;
+/// Time the app has to continue a YMODEM batch. Senders give up on the
+/// batch not long after this.
+const PAUSE_TIMEOUT_MS: u32 = 10_000;
+/// Polls before giving up on a sender that never starts.
+const MAX_POLLS: usize = 20;
+/// CRC requests before falling back to checksums.
+const CRC_POLLS: usize = 3;
+/// Consecutive bad blocks or timeouts before cancelling the transfer.
+const MAX_ERRORS: usize = 10;
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Idle,
+    /// Waiting for the start of a block or the end of the file.
+    Start,
+    /// Receiving a block.
+    Block,
+    /// Waiting for the app to continue a YMODEM batch.
+    Paused,
+}
+
+pub struct App {
+    callback: Option<Callback>,
+    buffer: Option<AppSlice<Shared, u8>>,
+    header: Option<AppSlice<Shared, u8>>,
+    /// Set while this app runs the transfer. A process recreated in the
+    /// same slot gets a fresh grant, so it doesn't inherit the transfer.
+    receiving: bool,
+}
+
+impl Default for App {
+    fn default() -> App {
+        App {
+            callback: None,
+            buffer: None,
+            header: None,
+            receiving: false,
+        }
+    }
+}
+
+pub struct XModem<'a, U: UART + 'a, A: Alarm + 'a> {
+    console: &'a XConsole<'a, U>,
+    alarm: &'a A,
+    apps: Grant<App>,
+    app: Cell<Option<AppId>>,
+    state: Cell<State>,
+    ymodem: Cell<bool>,
+    crc: Cell<bool>,
+    started: Cell<bool>,
+    header_next: Cell<bool>,
+    block: TakeCell<'static, [u8]>,
+    block_size: Cell<usize>,
+    block_idx: Cell<usize>,
+    expected: Cell<u8>,
+    received: Cell<usize>,
+    file_size: Cell<Option<usize>>,
+    errors: Cell<usize>,
+    eot_seen: Cell<bool>,
+    can_seen: Cell<bool>,
+}
+
+impl<'a, U: UART, A: Alarm> XModem<'a, U, A> {
+    pub fn new(console: &'a XConsole<'a, U>,
+               alarm: &'a A,
+               block: &'static mut [u8],
+               container: Grant<App>)
+               -> XModem<'a, U, A> {
+        XModem {
+            console: console,
+            alarm: alarm,
+            apps: container,
+            app: Cell::new(None),
+            state: Cell::new(State::Idle),
+            ymodem: Cell::new(false),
+            crc: Cell::new(true),
+            started: Cell::new(false),
+            header_next: Cell::new(false),
+            block: TakeCell::new(block),
+            block_size: Cell::new(0),
+            block_idx: Cell::new(0),
+            expected: Cell::new(1),
+            received: Cell::new(0),
+            file_size: Cell::new(None),
+            errors: Cell::new(0),
+            eot_seen: Cell::new(false),
+            can_seen: Cell::new(false),
+        }
+    }
+
+    fn start(&self, appid: AppId, ymodem: bool) -> ReturnCode {
+        if self.owner().is_some() {
+            return ReturnCode::EBUSY;
+        }
+        let has_buffer = self.apps.enter(appid, |app, _| {
+            app.receiving = app.buffer.is_some();
+            app.receiving
+        }).unwrap_or(false);
+        if !has_buffer {
+            return ReturnCode::ERESERVE;
+        }
+
+        self.app.set(Some(appid));
+        self.ymodem.set(ymodem);
+        self.crc.set(true);
+        self.next_file();
+        ReturnCode::SUCCESS
+    }
+
+    /// The app running the transfer. If it has exited or been restarted,
+    /// the transfer is cancelled without a callback.
+    fn owner(&self) -> Option<AppId> {
+        self.app.get().and_then(|appid| {
+            let alive = self.apps.enter(appid, |app, _| app.receiving).unwrap_or(false);
+            if alive {
+                Some(appid)
+            } else {
+                self.console.write_bytes(&[CAN, CAN]);
+                self.state.set(State::Idle);
+                self.alarm.disable();
+                self.app.set(None);
+                None
+            }
+        })
+    }
+
+    /// Starts receiving a file: the only one for XMODEM, or the next one in
+    /// a YMODEM batch.
+    fn next_file(&self) {
+        let ymodem = self.ymodem.get();
+        self.header_next.set(ymodem);
+        self.expected.set(if ymodem { 0 } else { 1 });
+        self.started.set(false);
+        self.received.set(0);
+        self.file_size.set(None);
+        self.errors.set(0);
+        self.eot_seen.set(false);
+        self.can_seen.set(false);
+        self.state.set(State::Start);
+        self.poll();
+    }
+
+    /// Asks the sender to start, which also selects CRC or checksum mode.
+    fn poll(&self) {
+        self.console.write_bytes(&[if self.crc.get() { CRC_REQUEST } else { NAK }]);
+        self.set_timeout(POLL_TIMEOUT_MS);
+    }
+
+    fn set_timeout(&self, ms: u32) {
+        let ticks = A::Frequency::frequency() / 1000 * ms;
+        self.alarm.set_alarm(self.alarm.now().wrapping_add(ticks));
+    }
+
+    fn ack(&self) {
+        self.errors.set(0);
+        self.console.write_bytes(&[ACK]);
+        self.set_timeout(POLL_TIMEOUT_MS);
+    }
+
+    /// Rejects the current block, cancelling if the sender keeps failing.
+    fn nak(&self) {
+        self.state.set(State::Start);
+        self.errors.set(self.errors.get() + 1);
+        if self.errors.get() >= MAX_ERRORS {
+            self.cancel(ReturnCode::FAIL);
+            return;
+        }
+        if self.started.get() {
+            self.console.write_bytes(&[NAK]);
+            self.set_timeout(POLL_TIMEOUT_MS);
+        } else {
+            self.poll();
+        }
+    }
+
+    fn cancel(&self, result: ReturnCode) {
+        self.console.write_bytes(&[CAN, CAN]);
+        self.finish(result);
+    }
+
+    /// Ends the transfer and reports `result` to the app.
+    fn finish(&self, result: ReturnCode) {
+        self.state.set(State::Idle);
+        self.alarm.disable();
+        self.app.take().map(|appid| {
+            self.report(appid, result, 0);
+        });
+    }
+
+    fn report(&self, appid: AppId, result: ReturnCode, more: usize) {
+        let len = self.received.get();
+        let _ = self.apps.enter(appid, |app, _| {
+            if !app.receiving {
+                return;
+            }
+            app.receiving = more != 0;
+            app.callback.map(|mut cb| {
+                cb.schedule(isize::from(result) as usize, len, more);
+            });
+        });
+    }
+
+    fn start_block(&self, data_len: usize) {
+        let check_len = if self.crc.get() { 2 } else { 1 };
+        self.block_size.set(2 + data_len + check_len);
+        self.block_idx.set(0);
+        self.state.set(State::Block);
+        self.set_timeout(BYTE_TIMEOUT_MS);
+    }
+
+    fn end_of_file(&self) {
+        // YMODEM senders resend EOT after the first is rejected, which
+        // guards against a corrupted byte being taken as the end.
+        if self.ymodem.get() && !self.eot_seen.get() {
+            self.eot_seen.set(true);
+            self.console.write_bytes(&[NAK]);
+            self.set_timeout(POLL_TIMEOUT_MS);
+            return;
+        }
+
+        self.console.write_bytes(&[ACK]);
+        if self.ymodem.get() {
+            self.state.set(State::Paused);
+            self.set_timeout(PAUSE_TIMEOUT_MS);
+            self.owner().map(|appid| {
+                self.report(appid, ReturnCode::SUCCESS, 1);
+            });
+        } else {
+            self.finish(ReturnCode::SUCCESS);
+        }
+    }
+
+    fn block_received(&self) {
+        self.state.set(State::Start);
+        let data_len = self.block_size.get() - if self.crc.get() { 4 } else { 3 };
+
+        let valid = self.block.map_or(false, |block| {
+            let data = &block[2..2 + data_len];
+            let check_ok = if self.crc.get() {
+                let sent = (block[2 + data_len] as u16) << 8 | block[3 + data_len] as u16;
+                crc16(data) == sent
+            } else {
+                data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b)) == block[2 + data_len]
+            };
+            block[0] == !block[1] && check_ok
+        });
+        if !valid {
+            self.nak();
+            return;
+        }
+
+        let number = self.block.map_or(0, |block| block[0]);
+        let expected = self.expected.get();
+        if number == expected.wrapping_sub(1) {
+            // The sender missed our acknowledgement and resent the block.
+            self.ack();
+            return;
+        }
+        if number != expected {
+            self.cancel(ReturnCode::FAIL);
+            return;
+        }
+        self.started.set(true);
+        self.expected.set(expected.wrapping_add(1));
+
+        if self.header_next.get() {
+            self.header_next.set(false);
+            self.header_received(data_len);
+        } else {
+            self.data_received(data_len);
+        }
+    }
+
+    /// Handles YMODEM block 0, which holds the file name and size. A header
+    /// with an empty name ends the batch.
+    fn header_received(&self, data_len: usize) {
+        let appid = match self.owner() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let end_of_batch = self.block.map_or(true, |block| block[2] == 0);
+        if end_of_batch {
+            self.console.write_bytes(&[ACK]);
+            self.finish(ReturnCode::SUCCESS);
+            return;
+        }
+
+        self.block.map(|block| {
+            let header = &block[2..2 + data_len];
+            self.file_size.set(parse_size(header));
+            let _ = self.apps.enter(appid, |app, _| {
+                app.header.as_mut().map(|dest| {
+                    let len = cmp::min(dest.len(), header.len());
+                    dest.as_mut()[..len].copy_from_slice(&header[..len]);
+                });
+            });
+        });
+
+        // Acknowledge the header, then ask for the file contents.
+        self.ack();
+        self.started.set(false);
+        self.poll();
+    }
+
+    fn data_received(&self, data_len: usize) {
+        let appid = match self.owner() {
+            Some(appid) => appid,
+            None => return,
+        };
+        let offset = self.received.get();
+        let len = match self.file_size.get() {
+            Some(size) => cmp::min(data_len, size.saturating_sub(offset)),
+            None => data_len,
+        };
+
+        let fits = self.block.map_or(false, |block| {
+            self.apps.enter(appid, |app, _| {
+                app.buffer.as_mut().map_or(false, |dest| {
+                    if offset + len > dest.len() {
+                        return false;
+                    }
+                    dest.as_mut()[offset..offset + len].copy_from_slice(&block[2..2 + len]);
+                    true
+                })
+            }).unwrap_or(false)
+        });
+        if !fits {
+            self.cancel(ReturnCode::ESIZE);
+            return;
+        }
+
+        self.received.set(offset + len);
+        self.ack();
+    }
+}
+
+/// CRC-16/XMODEM: polynomial 0x1021, initial value 0.
+fn crc16(data: &[u8]) -> u16 {
+    let mut crc: u16 = 0;
+    for byte in data {
+        crc ^= (*byte as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 { (crc << 1) ^ 0x1021 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+/// Reads the decimal file size that follows the file name in a YMODEM
+/// header.
+fn parse_size(header: &[u8]) -> Option<usize> {
+    let name_end = header.iter().position(|b| *b == 0)?;
+    let mut size = None;
+    for byte in &header[name_end + 1..] {
+        match *byte {
+            b'0'...b'9' => {
+                size = Some(size.unwrap_or(0) * 10 + (*byte - b'0') as usize);
+            }
+            _ => break,
+        }
+    }
+    size
+}
+
+impl<'a, U: UART, A: Alarm> InputClient for XModem<'a, U, A> {
+    fn received(&self, byte: u8) -> bool {
+        match self.state.get() {
+            State::Start => {
+                let can = byte == CAN;
+                if can && self.can_seen.get() {
+                    self.finish(ReturnCode::ECANCEL);
+                    return true;
+                }
+                self.can_seen.set(can);
+
+                match byte {
+                    SOH => self.start_block(128),
+                    STX => self.start_block(1024),
+                    EOT if !self.header_next.get() => self.end_of_file(),
+                    // Anything else is line noise.
+                    _ => {}
+                }
+            }
+            State::Block => {
+                let idx = self.block_idx.get();
+                self.block.map(|block| block[idx] = byte);
+                self.block_idx.set(idx + 1);
+                if idx + 1 == self.block_size.get() {
+                    self.block_received();
+                } else {
+                    self.set_timeout(BYTE_TIMEOUT_MS);
+                }
+            }
+            State::Paused => {
+                // Input stays with the transfer only while its app is
+                // around to continue it.
+                if self.owner().is_none() {
+                    return false;
+                }
+            }
+            State::Idle => return false,
+        }
+        true
+    }
+}
+
+impl<'a, U: UART, A: Alarm> time::Client for XModem<'a, U, A> {
+    fn fired(&self) {
+        if self.owner().is_none() {
+            return;
+        }
+        match self.state.get() {
+            State::Start | State::Block => {}
+            State::Paused => {
+                // The app never continued the batch.
+                self.cancel(ReturnCode::FAIL);
+                return;
+            }
+            State::Idle => return,
+        }
+
+        if self.started.get() {
+            self.nak();
+            return;
+        }
+
+        // Still waiting for the sender to start.
+        self.state.set(State::Start);
+        self.errors.set(self.errors.get() + 1);
+        if self.errors.get() >= MAX_POLLS {
+            self.cancel(ReturnCode::FAIL);
+            return;
+        }
+        if !self.ymodem.get() && self.errors.get() >= CRC_POLLS {
+            self.crc.set(false);
+        }
+        self.poll();
+    }
+}
+
+impl<'a, U: UART, A: Alarm> Driver for XModem<'a, U, A> {
+    /// Setup shared buffers.
+    ///
+    /// ### `allow_num`
+    ///
+    /// - `0`: Buffer that receives the file
+    /// - `1`: Optional buffer that receives each YMODEM file header
+    fn allow(&self, appid: AppId, allow_num: usize, slice: Option<AppSlice<Shared, u8>>) -> ReturnCode {
+        match allow_num {
+            0 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.buffer = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            1 => {
+                self.apps
+                    .enter(appid, |app, _| {
+                        app.header = slice;
+                        ReturnCode::SUCCESS
+                    })
+                    .unwrap_or_else(|err| err.into())
+            }
+            _ => ReturnCode::ENOSUPPORT,
+        }
+    }
+
+    /// Setup callbacks.
+    ///
+    /// ### `subscribe_num`
+    ///
+    /// - `0`: Transfer or YMODEM file complete
+    fn subscribe(&self, subscribe_num: usize, callback: Option<Callback>, app_id: AppId) -> ReturnCode {
+        match subscribe_num {
+            0 => {
+                self.apps.enter(app_id, |app, _| {
+                    app.callback = callback;
+                    ReturnCode::SUCCESS
+                }).unwrap_or_else(|err| err.into())
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+
+    /// Transfer control
+    ///
+    /// ### `command_num`
+    ///
+    /// - `0`: Driver check.
+    /// - `1`: Start receiving: `arg1` is 0 for XMODEM or 1 for a YMODEM
+    ///        batch.
+    /// - `2`: Receive the next file of a YMODEM batch.
+    /// - `3`: Cancel the transfer.
+    fn command(&self, cmd_num: usize, arg1: usize, _: usize, appid: AppId) -> ReturnCode {
+        match cmd_num {
+            0 /* check if present */ => ReturnCode::SUCCESS,
+            1 /* start */ => {
+                match arg1 {
+                    0 => self.start(appid, false),
+                    1 => self.start(appid, true),
+                    _ => ReturnCode::EINVAL,
+                }
+            },
+            2 /* next file */ => {
+                if self.owner() != Some(appid) || self.state.get() != State::Paused {
+                    return ReturnCode::EINVAL;
+                }
+                self.next_file();
+                ReturnCode::SUCCESS
+            },
+            3 /* cancel */ => {
+                if self.owner() != Some(appid) {
+                    return ReturnCode::EINVAL;
+                }
+                self.cancel(ReturnCode::ECANCEL);
+                ReturnCode::SUCCESS
+            },
+            _ => ReturnCode::ENOSUPPORT
+        }
+    }
+}
@@ -1,10 +1,42 @@
+use core::cmp;
 use cortexm4;
+use clock;
 use kernel::Chip;
 use pit;
 use spi;
 use gpio;
 use uart;
 use mpu;
+use nvic;
+use wdog;
+use ewm;
+use llwu;
+use smc::{self, SleepMode, SleepVeto};
+
+/// Told when a process faults. The fault handler pends the SOFTWARE
+/// interrupt, so the client runs from `service_pending_interrupts` like any
+/// other interrupt handler.
+pub trait ProcessFaultClient {
+    /// `region` is the `mpu::process_region` of the process that faulted.
+    fn process_faulted(&self, region: u32);
+}
+
+static mut PROCESS_FAULT_CLIENT: Option<&'static ProcessFaultClient> = None;
+
+/// `mpu::process_region` of the process that last faulted.
+static mut FAULTED_REGION: u32 = 0;
+
+/// Called by the hard fault handler when the running process faults.
+pub unsafe fn process_fault() {
+    FAULTED_REGION = mpu::process_region();
+    nvic::set_pending(nvic::NvicIdx::SOFTWARE);
+}
+
+pub fn set_process_fault_client(client: &'static ProcessFaultClient) {
+    unsafe {
+        PROCESS_FAULT_CLIENT = Some(client);
+    }
+}
 
 pub struct MK66 {
     pub mpu: mpu::Mpu,
@@ -16,11 +48,35 @@ impl MK66 {
         // Set up DMA channels
         // TODO: implement
 
+        // Keep peripheral dividers right when the clocks change.
+        clock::register_client(&uart::UART0);
+        clock::register_client(&uart::UART1);
+        clock::register_client(&uart::UART2);
+        clock::register_client(&uart::UART3);
+        clock::register_client(&uart::UART4);
+        clock::register_client(&spi::SPI0);
+        clock::register_client(&spi::SPI1);
+        clock::register_client(&spi::SPI2);
+        clock::register_client(&pit::PIT);
+
         MK66 {
             mpu: mpu::Mpu::new(),
             systick: ()
         }
     }
+
+    /// The deepest sleep mode every active peripheral allows.
+    fn deepest_sleep(&self) -> SleepMode {
+        unsafe {
+            let vetoes: [&SleepVeto; 9] = [&uart::UART0, &uart::UART1, &uart::UART2,
+                                           &uart::UART3, &uart::UART4,
+                                           &spi::SPI0, &spi::SPI1, &spi::SPI2,
+                                           &pit::PIT];
+            vetoes.iter().fold(smc::deepest_sleep(), |mode, veto| {
+                cmp::min(mode, veto.deepest_sleep())
+            })
+        }
+    }
 }
 
 impl Chip for MK66 {
@@ -29,8 +85,13 @@ impl Chip for MK66 {
 
     fn service_pending_interrupts(&mut self) {
         use nvic::*;
+
+        // The kernel loop calls this on every pass, so refreshing the
+        // watchdog here resets the chip if the loop ever stops.
+        wdog::service();
+
         unsafe {
-            while let Some(interrupt) = cortexm4::nvic::next_pending() {
+            while let Some(interrupt) = next_pending() {
                 match interrupt {
                     PCMA => gpio::PA.handle_interrupt(),
                     PCMB => gpio::PB.handle_interrupt(),
@@ -43,6 +104,14 @@ impl Chip for MK66 {
                     SPI2 => spi::SPI2.handle_interrupt(),
                     UART0 => uart::UART0.handle_interrupt(),
                     UART1 => uart::UART1.handle_interrupt(),
+                    UART2 => uart::UART2.handle_interrupt(),
+                    UART3 => uart::UART3.handle_interrupt(),
+                    UART4 => uart::UART4.handle_interrupt(),
+                    WDOG => ewm::EWM.handle_interrupt(),
+                    LLWU => llwu::LLWU.handle_interrupt(),
+                    SOFTWARE => {
+                        PROCESS_FAULT_CLIENT.map(|client| client.process_faulted(FAULTED_REGION));
+                    }
                     _ => {}
                 }
 
@@ -66,6 +135,9 @@ impl Chip for MK66 {
     }
 
     fn sleep(&self) {
+        unsafe {
+            smc::sleep(self.deepest_sleep());
+        }
     }
 
     unsafe fn atomic<F, R>(&self, f: F) -> R
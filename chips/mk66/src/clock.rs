@@ -4,11 +4,62 @@ static mut MCGOUTCLK: u32 = 20_480_000;
 
 static mut CORECLK: u32 = 20_480_000;
 static mut BUSCLK: u32 = 20_480_000;
+static mut FLEXBUSCLK: u32 = 20_480_000;
 static mut FLASHCLK: u32 = 10_240_000;
 
-use osc;
+use core::cmp;
+use core::ptr;
+use lptmr;
 use mcg;
 use sim;
+use smc;
+use osc;
+use rtc;
+
+/// The clock frequencies, in Hz.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Clocks {
+    pub mcgout: u32,
+    pub core: u32,
+    pub bus: u32,
+    pub flexbus: u32,
+    pub flash: u32,
+}
+
+/// Notified after the clocks change, so that drivers can recompute
+/// dividers that depend on them.
+pub trait ClockClient {
+    fn clocks_changed(&self, previous: Clocks);
+}
+
+const MAX_CLIENTS: usize = 16;
+
+/// Frequency of the fast internal reference, used in VLPR. Selecting it
+/// with `use_internal` also sets its divider (FCRDIV) to one.
+const FAST_IRC_HZ: u32 = 4_000_000;
+
+static mut CLIENTS: [Option<&'static ClockClient>; MAX_CLIENTS] = [None; MAX_CLIENTS];
+
+pub fn register_client(client: &'static ClockClient) {
+    unsafe {
+        match CLIENTS.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(client),
+            None => panic!("Too many clock clients"),
+        }
+    }
+}
+
+pub fn clocks() -> Clocks {
+    unsafe {
+        Clocks {
+            mcgout: MCGOUTCLK,
+            core: CORECLK,
+            bus: BUSCLK,
+            flexbus: FLEXBUSCLK,
+            flash: FLASHCLK,
+        }
+    }
+}
 
 pub fn peripheral_clock_hz() -> u32 {
     unsafe { BUSCLK }
@@ -18,6 +69,10 @@ pub fn bus_clock_hz() -> u32 {
     unsafe { BUSCLK }
 }
 
+pub fn flexbus_clock_hz() -> u32 {
+    unsafe { FLEXBUSCLK }
+}
+
 pub fn flash_clock_hz() -> u32 {
     unsafe { FLASHCLK }
 }
@@ -29,69 +84,559 @@ pub fn core_clock_hz() -> u32 {
 #[allow(non_upper_case_globals)]
 const MHz: u32 = 1_000_000;
 
-pub fn configure(core_freq: u32) {
-    if let mcg::State::Fei(fei) = mcg::state() {
-
-        let (pll_mul, pll_div) = match core_freq {
-            16 => (16, 8),
-            20 => (20, 8),
-            24 => (24, 8),
-            28 => (28, 8),
-
-            32 => (16, 4),
-            36 => (18, 4),
-            40 => (20, 4),
-            44 => (22, 4),
-            48 => (24, 4),
-            52 => (26, 4),
-            56 => (28, 4),
-            60 => (30, 4),
-
-            64 => (16, 2),
-            68 => (17, 2),
-            72 => (18, 2),
-            76 => (19, 2),
-            80 => (20, 2),
-            84 => (21, 2),
-            88 => (22, 2),
-            92 => (23, 2),
-            96 => (24, 2),
-            100 => (25, 2),
-            104 => (26, 2),
-            108 => (27, 2),
-            112 => (28, 2),
-            116 => (29, 2),
-            120 => (30, 2),
-            _ => panic!("Invalid core frequency selected!")
-        };
+/// Where MCGOUTCLK comes from.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// The PLL, referenced to the crystal. The PLL frequency and the core
+    /// divider are chosen to give the core frequency.
+    Pll,
+    /// The FLL, referenced to the crystal divided by FRDIV, at its reset
+    /// multiplier of 640.
+    Fll,
+}
+
+/// A clock tree running from the crystal. The core runs at exactly
+/// `core_hz`; the bus, FlexBus and flash clocks run as fast as they can
+/// without going over their targets.
+pub struct ClockConfig {
+    pub xtal: mcg::Xtal,
+    pub source: Source,
+    pub core_hz: u32,
+    pub bus_hz: u32,
+    pub flexbus_hz: u32,
+    pub flash_hz: u32,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Domain {
+    Core,
+    Bus,
+    FlexBus,
+    Flash,
+}
+
+/// Why a `ClockConfig` can't be used. Frequencies are in Hz.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ClockError {
+    /// The crystal frequency is outside the oscillator range it is set up
+    /// for.
+    CrystalOutOfRange { freq: u32, min: u32, max: u32 },
+    /// FRDIV doesn't bring the crystal into the FLL's reference range. The
+    /// FLL keeps running while the PLL is set up, so this matters even
+    /// when the PLL is used.
+    FllReferenceOutOfRange { reference: u32, min: u32, max: u32 },
+    /// No PLL setting divides down to the core frequency while keeping the
+    /// PLL reference between 8 and 16 MHz and its output between 90 and
+    /// 180 MHz.
+    NoPllSetting { core: u32 },
+    /// The FLL output can't be divided down to the core frequency.
+    CoreNotReachable { mcgout: u32, core: u32 },
+    /// A target is above the chip's limit for that clock.
+    TooFast { domain: Domain, target: u32, max: u32 },
+    /// A target is below the slowest clock the dividers can produce.
+    TooSlow { domain: Domain, target: u32, min: u32 },
+}
+
+/// Maximum frequencies of each clock in one run mode.
+struct Limits {
+    core: u32,
+    bus: u32,
+    flexbus: u32,
+    flash: u32,
+}
+
+const RUN_LIMITS: Limits = Limits {
+    core: 120 * MHz,
+    bus: 60 * MHz,
+    flexbus: 50 * MHz,
+    flash: 28 * MHz,
+};
+
+const HSRUN_LIMITS: Limits = Limits {
+    core: 180 * MHz,
+    bus: 60 * MHz,
+    flexbus: 60 * MHz,
+    flash: 28 * MHz,
+};
+
+const FLL_FACTOR: u32 = 640;
+const FLL_REFERENCE_MIN: u32 = 31_250;
+const FLL_REFERENCE_MAX: u32 = 39_062;
+
+const PLL_REFERENCE_MIN: u32 = 8 * MHz;
+const PLL_REFERENCE_MAX: u32 = 16 * MHz;
+const PLL_MIN: u32 = 90 * MHz;
+const PLL_MAX: u32 = 180 * MHz;
+
+const MAX_DIVIDER: u32 = 16;
 
-        let mut bus_div = 1;
-        while core_freq / bus_div > 60 {
-            bus_div += 1;
+/// The settings that produce a `ClockConfig`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ClockTree {
+    /// PLL multiplier and reference divider, or None to run from the FLL.
+    pub pll: Option<(u8, u8)>,
+    pub mcgout: u32,
+    pub core_div: u32,
+    pub bus_div: u32,
+    pub flexbus_div: u32,
+    pub flash_div: u32,
+}
+
+impl ClockTree {
+    pub fn clocks(&self) -> Clocks {
+        Clocks {
+            mcgout: self.mcgout,
+            core: self.mcgout / self.core_div,
+            bus: self.mcgout / self.bus_div,
+            flexbus: self.mcgout / self.flexbus_div,
+            flash: self.mcgout / self.flash_div,
         }
+    }
+
+    /// Above 120 MHz the chip has to be in HSRUN.
+    pub fn high_speed_run(&self) -> bool {
+        self.mcgout / self.core_div > RUN_LIMITS.core
+    }
+}
+
+/// The crystal divided by FRDIV, which references the FLL.
+fn fll_reference(xtal: &mcg::Xtal) -> u32 {
+    use mcg::Frdiv::*;
+
+    let low_range = match xtal.range {
+        mcg::OscRange::Low => true,
+        _ => false,
+    };
+    let ratio = match xtal.frdiv {
+        Low1_High32 => if low_range { 1 } else { 32 },
+        Low2_High64 => if low_range { 2 } else { 64 },
+        Low4_High128 => if low_range { 4 } else { 128 },
+        Low8_High256 => if low_range { 8 } else { 256 },
+        Low16_High512 => if low_range { 16 } else { 512 },
+        Low32_High1024 => if low_range { 32 } else { 1024 },
+        Low64_High1280 => if low_range { 64 } else { 1280 },
+        Low128_High1536 => if low_range { 128 } else { 1536 },
+    };
+    xtal.freq / ratio
+}
+
+/// Finds a PLL multiplier, reference divider and core divider that run the
+/// core at exactly `core`, preferring the smallest core divider.
+fn solve_pll(xtal: u32, core: u32) -> Option<(u8, u8, u32)> {
+    for core_div in 1..MAX_DIVIDER + 1 {
+        let pll = core * core_div;
+        if pll < PLL_MIN || pll > PLL_MAX {
+            continue;
+        }
+
+        for prdiv in 1..9 {
+            let reference = xtal / prdiv;
+            if xtal % prdiv != 0 || reference < PLL_REFERENCE_MIN || reference > PLL_REFERENCE_MAX {
+                continue;
+            }
+
+            // MCGPLLCLK is the VCO divided by two.
+            let vco = 2 * pll;
+            let multiplier = vco / reference;
+            if vco % reference == 0 && multiplier >= 16 && multiplier <= 47 {
+                return Some((multiplier as u8, prdiv as u8, core_div));
+            }
+        }
+    }
+    None
+}
+
+fn check_limit(domain: Domain, target: u32, max: u32) -> Result<(), ClockError> {
+    if target > max {
+        Err(ClockError::TooFast { domain: domain, target: target, max: max })
+    } else {
+        Ok(())
+    }
+}
+
+/// The smallest divider that keeps a clock at or below `target`. The core
+/// clock has to be an integer multiple of every other clock, so the
+/// divider is a multiple of the core divider.
+fn divider(domain: Domain, mcgout: u32, core_div: u32, target: u32) -> Result<u32, ClockError> {
+    let mut div = core_div;
+    while div <= MAX_DIVIDER && mcgout > target * div {
+        div += core_div;
+    }
+
+    if div > MAX_DIVIDER {
+        let slowest = mcgout / (MAX_DIVIDER / core_div * core_div);
+        Err(ClockError::TooSlow { domain: domain, target: target, min: slowest })
+    } else {
+        Ok(div)
+    }
+}
 
-        let mut flash_div = 1;
-        while core_freq / flash_div > 28 {
-            flash_div += 1;
+/// Works out the MCG and SIM settings for `config`, checking them against
+/// the chip's limits. Touches no registers.
+pub fn solve(config: &ClockConfig) -> Result<ClockTree, ClockError> {
+    let xtal = &config.xtal;
+
+    let (min, max) = match xtal.range {
+        mcg::OscRange::Low => (32_000, 40_000),
+        mcg::OscRange::High => (3 * MHz, 8 * MHz),
+        mcg::OscRange::VeryHigh => (8 * MHz, 32 * MHz),
+    };
+    if xtal.freq < min || xtal.freq > max {
+        return Err(ClockError::CrystalOutOfRange { freq: xtal.freq, min: min, max: max });
+    }
+
+    let reference = fll_reference(xtal);
+    if reference < FLL_REFERENCE_MIN || reference > FLL_REFERENCE_MAX {
+        return Err(ClockError::FllReferenceOutOfRange {
+            reference: reference,
+            min: FLL_REFERENCE_MIN,
+            max: FLL_REFERENCE_MAX,
+        });
+    }
+
+    let limits = if config.core_hz > RUN_LIMITS.core { &HSRUN_LIMITS } else { &RUN_LIMITS };
+    check_limit(Domain::Core, config.core_hz, limits.core)?;
+    check_limit(Domain::Bus, config.bus_hz, limits.bus)?;
+    check_limit(Domain::FlexBus, config.flexbus_hz, limits.flexbus)?;
+    check_limit(Domain::Flash, config.flash_hz, limits.flash)?;
+
+    let (pll, mcgout, core_div) = match config.source {
+        Source::Pll => {
+            match solve_pll(xtal.freq, config.core_hz) {
+                Some((multiplier, prdiv, core_div)) => {
+                    (Some((multiplier, prdiv)), config.core_hz * core_div, core_div)
+                }
+                None => return Err(ClockError::NoPllSetting { core: config.core_hz }),
+            }
         }
+        Source::Fll => {
+            let mcgout = reference * FLL_FACTOR;
+            if config.core_hz == 0 || mcgout % config.core_hz != 0 ||
+               mcgout / config.core_hz > MAX_DIVIDER {
+                return Err(ClockError::CoreNotReachable { mcgout: mcgout, core: config.core_hz });
+            }
+            (None, mcgout, mcgout / config.core_hz)
+        }
+    };
+
+    let bus_div = divider(Domain::Bus, mcgout, core_div, config.bus_hz)?;
+    // FlexBus can't run faster than the bus.
+    let flexbus_target = cmp::min(config.flexbus_hz, mcgout / bus_div);
+    let flexbus_div = divider(Domain::FlexBus, mcgout, core_div, flexbus_target)?;
+    let flash_div = divider(Domain::Flash, mcgout, core_div, config.flash_hz)?;
 
-        osc::enable(mcg::xtals::Teensy16MHz);
-        sim::set_dividers(1, bus_div, flash_div);
+    Ok(ClockTree {
+        pll: pll,
+        mcgout: mcgout,
+        core_div: core_div,
+        bus_div: bus_div,
+        flexbus_div: flexbus_div,
+        flash_div: flash_div,
+    })
+}
 
-        let fbe = fei.use_xtal(mcg::xtals::Teensy16MHz);
-        let pbe = fbe.enable_pll(pll_mul, pll_div);
-        pbe.use_pll();
+/// Brings the MCG to FBE from whatever mode it is in, so that MCGOUTCLK
+/// comes straight from the crystal.
+fn enter_fbe(xtal: mcg::Xtal) -> mcg::Fbe {
+    match mcg::state() {
+        mcg::State::Fei(fei) => fei.use_xtal(xtal),
+        mcg::State::Fee(fee) => fee.use_external(),
+        mcg::State::Fbi(fbi) => fbi.use_xtal(xtal),
+        mcg::State::Fbe(fbe) => fbe,
+        mcg::State::Pbe(pbe) => pbe.disable_pll(),
+        mcg::State::Pee(pee) => pee.bypass_pll().disable_pll(),
+        mcg::State::Blpi(blpi) => blpi.exit_low_power().use_xtal(xtal),
+        mcg::State::Blpe(blpe) => match blpe.exit_low_power() {
+            mcg::State::Pbe(pbe) => pbe.disable_pll(),
+            mcg::State::Fbe(fbe) => fbe,
+            _ => unreachable!(),
+        },
+        mcg::State::Stop => panic!("MCG is stopped"),
+    }
+}
+
+fn set_dividers(tree: &ClockTree) {
+    sim::set_dividers(tree.core_div, tree.bus_div, tree.flexbus_div, tree.flash_div);
+}
 
+fn set_clocks(tree: &ClockTree) {
+    let previous = clocks();
+
+    set_dividers(tree);
+    let current = tree.clocks();
+    unsafe {
+        MCGOUTCLK = current.mcgout;
+        CORECLK = current.core;
+        BUSCLK = current.bus;
+        FLEXBUSCLK = current.flexbus;
+        FLASHCLK = current.flash;
+    }
+
+    if current != previous {
         unsafe {
-            MCGOUTCLK = core_freq * MHz;
-            CORECLK = core_freq * MHz;
-            BUSCLK = (core_freq * MHz) / bus_div; 
-            FLASHCLK = (core_freq * MHz) / flash_div;
+            for client in CLIENTS.iter().filter_map(|client| *client) {
+                client.clocks_changed(previous);
+            }
         }
+    }
+}
 
-    } else {
-        // We aren't in FEI mode, meaning that configuration has already occurred.
-        // For now, just exit without changing the existing configuration.
+/// Runs the clocks as described by `config`, from whatever mode they are
+/// in, and returns the resulting frequencies. Registered clock clients are
+/// notified once the new clocks are running. If the configuration is
+/// invalid, the clocks are left alone.
+pub fn configure(config: ClockConfig) -> Result<Clocks, ClockError> {
+    let tree = solve(&config)?;
+
+    // The MCG can't change modes in VLPR.
+    if smc::very_low_power_run() {
+        smc::exit_very_low_power_run();
+    }
+
+    // Run from the crystal while the PLL is reprogrammed. The crystal is
+    // slow enough for any divider and for leaving HSRUN.
+    let fbe = enter_fbe(config.xtal);
+
+    if tree.high_speed_run() {
+        smc::enter_high_speed_run();
+    } else if smc::high_speed_run() {
+        smc::exit_high_speed_run();
+    }
+
+    match tree.pll {
+        Some((multiplier, prdiv)) => {
+            let pbe = fbe.enable_pll(multiplier, prdiv);
+            set_dividers(&tree);
+            pbe.use_pll();
+        }
+        None => {
+            set_dividers(&tree);
+            fbe.use_fll_external();
+        }
+    }
+    set_clocks(&tree);
+    Ok(tree.clocks())
+}
+
+/// Drops to VLPR, with the core and bus running at 4 MHz from the fast
+/// internal reference and flash at 1 MHz. `configure` returns to RUN.
+/// Registered clock clients are notified once the new clocks are running.
+/// `xtal` is only started if the MCG has to pass through FBE to get there.
+pub fn configure_vlpr(xtal: mcg::Xtal) {
+    if smc::very_low_power_run() {
         return;
     }
+
+    // Flash may run at 1 MHz at most in VLPR.
+    let tree = ClockTree {
+        pll: None,
+        mcgout: FAST_IRC_HZ,
+        core_div: 1,
+        bus_div: 1,
+        flexbus_div: 1,
+        flash_div: 4,
+    };
+
+    let fbe = enter_fbe(xtal);
+    if smc::high_speed_run() {
+        smc::exit_high_speed_run();
+    }
+
+    set_dividers(&tree);
+    fbe.use_internal(mcg::InternalReference::FastInternal)
+       .enter_low_power();
+
+    smc::enter_very_low_power_run();
+    set_clocks(&tree);
+}
+
+/// Drives `source` onto the CLKOUT pin, starting it first if it isn't
+/// running. The pin still has to be muxed to CLKOUT.
+pub fn route_clkout(source: sim::ClkOut) {
+    match source {
+        sim::ClkOut::FlexBus => {
+            use sim::{clocks, Clock};
+            clocks::FLEXBUS.enable();
+        }
+        sim::ClkOut::Mcgirclk => mcg::enable_internal_reference_clock(),
+        sim::ClkOut::Oscerclk => osc::enable_external_reference_clock(),
+        sim::ClkOut::Rtc => rtc::enable_oscillator(),
+        _ => {}
+    }
+    sim::set_clkout(source);
+}
+
+/// A clock of known frequency to measure the core clock against.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Reference {
+    /// The 1 kHz LPO. Always running, but only accurate to a few percent.
+    Lpo,
+    /// The 32.768 kHz RTC crystal. Accurate, but started only if it isn't
+    /// running already, and then only usable once it has stabilized.
+    Rtc,
+}
+
+const DEMCR: *mut u32 = 0xE000EDFC as *mut u32;
+const DEMCR_TRCENA: u32 = 1 << 24;
+const DWT_CTRL: *mut u32 = 0xE0001000 as *mut u32;
+const DWT_CTRL_CYCCNTENA: u32 = 1;
+const DWT_CYCCNT: *const u32 = 0xE0001004 as *const u32;
+
+/// Measures the core clock, in Hz, by counting core cycles over `ticks`
+/// ticks of `reference`. Busy-waits the whole time, so call it at boot or
+/// with interrupts disabled, where nothing else can take cycles. Uses the
+/// LPTMR.
+pub fn measure_core_hz(reference: Reference, ticks: u16) -> u32 {
+    let (source, reference_hz) = match reference {
+        Reference::Lpo => (lptmr::ClockSource::Lpo, 1000),
+        Reference::Rtc => {
+            rtc::enable_oscillator();
+            sim::set_erclk32k(sim::Erclk32k::Rtc32k);
+            (lptmr::ClockSource::Erclk32k, 32768)
+        }
+    };
+
+    unsafe {
+        ptr::write_volatile(DEMCR, ptr::read_volatile(DEMCR) | DEMCR_TRCENA);
+        ptr::write_volatile(DWT_CTRL, ptr::read_volatile(DWT_CTRL) | DWT_CTRL_CYCCNTENA);
+    }
+
+    lptmr::start_counting(source);
+
+    // Start on a tick edge, so that only whole ticks are timed.
+    let first = lptmr::count();
+    while lptmr::count() == first {}
+    let start = unsafe { ptr::read_volatile(DWT_CYCCNT) };
+    let begin = lptmr::count();
+    while lptmr::count().wrapping_sub(begin) < ticks {}
+    let end = unsafe { ptr::read_volatile(DWT_CYCCNT) };
+
+    lptmr::stop();
+
+    let cycles = end.wrapping_sub(start) as u64;
+    (cycles * reference_hz / ticks as u64) as u32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mcg::xtals::Teensy16MHz;
+
+    fn pll(core_hz: u32, bus_hz: u32, flexbus_hz: u32, flash_hz: u32) -> ClockConfig {
+        ClockConfig {
+            xtal: Teensy16MHz,
+            source: Source::Pll,
+            core_hz: core_hz,
+            bus_hz: bus_hz,
+            flexbus_hz: flexbus_hz,
+            flash_hz: flash_hz,
+        }
+    }
+
+    #[test]
+    fn pll_run() {
+        // 16 MHz / 2 * 30 / 2 = 120 MHz.
+        assert_eq!(solve(&pll(120 * MHz, 60 * MHz, 50 * MHz, 28 * MHz)),
+                   Ok(ClockTree {
+                       pll: Some((30, 2)),
+                       mcgout: 120 * MHz,
+                       core_div: 1,
+                       bus_div: 2,
+                       flexbus_div: 3,
+                       flash_div: 5,
+                   }));
+    }
+
+    #[test]
+    fn pll_high_speed_run() {
+        let tree = solve(&pll(180 * MHz, 60 * MHz, 60 * MHz, 28 * MHz));
+        assert_eq!(tree,
+                   Ok(ClockTree {
+                       pll: Some((45, 2)),
+                       mcgout: 180 * MHz,
+                       core_div: 1,
+                       bus_div: 3,
+                       flexbus_div: 3,
+                       flash_div: 7,
+                   }));
+        assert!(tree.unwrap().high_speed_run());
+    }
+
+    #[test]
+    fn pll_below_its_range() {
+        // The PLL can't go below 90 MHz, so it runs at 96 MHz and the core
+        // divides it by two. Every other divider is a multiple of that.
+        assert_eq!(solve(&pll(48 * MHz, 48 * MHz, 48 * MHz, 24 * MHz)),
+                   Ok(ClockTree {
+                       pll: Some((24, 2)),
+                       mcgout: 96 * MHz,
+                       core_div: 2,
+                       bus_div: 2,
+                       flexbus_div: 2,
+                       flash_div: 4,
+                   }));
+    }
+
+    #[test]
+    fn fll() {
+        // 16 MHz / 512 * 640 = 20 MHz.
+        let config = ClockConfig {
+            source: Source::Fll,
+            ..pll(20 * MHz, 20 * MHz, 20 * MHz, 10 * MHz)
+        };
+        assert_eq!(solve(&config),
+                   Ok(ClockTree {
+                       pll: None,
+                       mcgout: 20 * MHz,
+                       core_div: 1,
+                       bus_div: 1,
+                       flexbus_div: 1,
+                       flash_div: 2,
+                   }));
+
+        let config = ClockConfig {
+            source: Source::Fll,
+            ..pll(7 * MHz, 7 * MHz, 7 * MHz, 7 * MHz)
+        };
+        assert_eq!(solve(&config),
+                   Err(ClockError::CoreNotReachable { mcgout: 20 * MHz, core: 7 * MHz }));
+    }
+
+    #[test]
+    fn no_pll_setting() {
+        assert_eq!(solve(&pll(97 * MHz, 60 * MHz, 50 * MHz, 28 * MHz)),
+                   Err(ClockError::NoPllSetting { core: 97 * MHz }));
+    }
+
+    #[test]
+    fn limits() {
+        assert_eq!(solve(&pll(200 * MHz, 60 * MHz, 60 * MHz, 28 * MHz)),
+                   Err(ClockError::TooFast { domain: Domain::Core, target: 200 * MHz, max: 180 * MHz }));
+        // FlexBus is limited to 50 MHz in RUN; only HSRUN allows 60 MHz.
+        assert_eq!(solve(&pll(120 * MHz, 60 * MHz, 60 * MHz, 28 * MHz)),
+                   Err(ClockError::TooFast { domain: Domain::FlexBus, target: 60 * MHz, max: 50 * MHz }));
+        assert_eq!(solve(&pll(120 * MHz, 60 * MHz, 50 * MHz, 40 * MHz)),
+                   Err(ClockError::TooFast { domain: Domain::Flash, target: 40 * MHz, max: 28 * MHz }));
+        // 120 MHz / 16 is the slowest the bus can go.
+        assert_eq!(solve(&pll(120 * MHz, 1 * MHz, 50 * MHz, 28 * MHz)),
+                   Err(ClockError::TooSlow { domain: Domain::Bus, target: 1 * MHz, min: 7_500_000 }));
+    }
+
+    #[test]
+    fn crystal() {
+        let config = ClockConfig {
+            xtal: mcg::Xtal { freq: 4 * MHz, ..Teensy16MHz },
+            ..pll(120 * MHz, 60 * MHz, 50 * MHz, 28 * MHz)
+        };
+        assert_eq!(solve(&config),
+                   Err(ClockError::CrystalOutOfRange { freq: 4 * MHz, min: 8 * MHz, max: 32 * MHz }));
+
+        // 16 MHz / 256 is too fast for the FLL.
+        let config = ClockConfig {
+            xtal: mcg::Xtal { frdiv: mcg::Frdiv::Low8_High256, ..Teensy16MHz },
+            ..pll(120 * MHz, 60 * MHz, 50 * MHz, 28 * MHz)
+        };
+        assert_eq!(solve(&config),
+                   Err(ClockError::FllReferenceOutOfRange { reference: 62_500, min: 31_250, max: 39_062 }));
+    }
 }
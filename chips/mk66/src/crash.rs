@@ -0,0 +1,124 @@
+//! Crash dumps that survive a reset.
+//!
+//! The hard fault handler saves the fault status registers, the stacked
+//! registers and the top of the faulting stack into RAM that is not
+//! initialized at boot (the `.noinit` section). SRAM keeps its contents
+//! across every reset except power-on and low-voltage, so after a watchdog,
+//! lockup or software reset the next boot can report what went wrong. A
+//! magic number and checksum tell a saved dump apart from leftover RAM.
+
+use core::mem;
+use core::ptr;
+use core::slice;
+
+/// Words of the faulting stack kept in a dump.
+pub const STACK_WORDS: usize = 64;
+
+const MAGIC: u32 = 0x4352_4153;
+
+/// SRAM_L and SRAM_U. A stack snapshot never crosses from one to the other.
+const SRAM_REGIONS: [(u32, u32); 2] = [(0x1FFF_0000, 0x2000_0000), (0x2000_0000, 0x2003_0000)];
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct CrashDump {
+    magic: u32,
+    /// r0-r3, r12, lr, pc and xPSR as stacked on exception entry.
+    pub stacked: [u32; 8],
+    /// Stack pointer at the time of the fault, before the exception frame.
+    pub sp: u32,
+    pub cfsr: u32,
+    pub hfsr: u32,
+    pub mmfar: u32,
+    pub bfar: u32,
+    /// Number of valid words in `stack`.
+    pub stack_len: u32,
+    /// Words from the faulting stack, starting just above the exception
+    /// frame.
+    pub stack: [u32; STACK_WORDS],
+    checksum: u32,
+}
+
+#[link_section = ".noinit"]
+static mut CRASH_DUMP: CrashDump = CrashDump {
+    magic: 0,
+    stacked: [0; 8],
+    sp: 0,
+    cfsr: 0,
+    hfsr: 0,
+    mmfar: 0,
+    bfar: 0,
+    stack_len: 0,
+    stack: [0; STACK_WORDS],
+    checksum: 0,
+};
+
+impl CrashDump {
+    pub fn pc(&self) -> u32 {
+        self.stacked[6]
+    }
+
+    pub fn lr(&self) -> u32 {
+        self.stacked[5]
+    }
+
+    pub fn xpsr(&self) -> u32 {
+        self.stacked[7]
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        // Every word except the checksum itself.
+        let len = (mem::size_of::<CrashDump>() / 4) - 1;
+        let words = unsafe { slice::from_raw_parts(self as *const CrashDump as *const u32, len) };
+        words.iter().fold(0x5A5A_5A5A, |sum, word| sum.rotate_left(1) ^ word)
+    }
+}
+
+/// Saves a dump of a kernel fault. `frame` points at the exception frame on
+/// the faulting stack.
+pub unsafe fn record(frame: *const u32) {
+    let dump = &mut CRASH_DUMP;
+
+    for i in 0..8 {
+        dump.stacked[i] = ptr::read_volatile(frame.offset(i as isize));
+    }
+    // The frame is 8 words, or 9 if the processor aligned the stack.
+    let aligned = (dump.stacked[7] & (1 << 9)) != 0;
+    let sp = frame as u32 + if aligned { 36 } else { 32 };
+    dump.sp = sp;
+
+    dump.cfsr = ptr::read_volatile(0xE000ED28 as *const u32);
+    dump.hfsr = ptr::read_volatile(0xE000ED2C as *const u32);
+    dump.mmfar = ptr::read_volatile(0xE000ED34 as *const u32);
+    dump.bfar = ptr::read_volatile(0xE000ED38 as *const u32);
+
+    // Only copy from the SRAM region the stack pointer is in, in case it
+    // has run off the end of memory.
+    let limit = SRAM_REGIONS.iter()
+        .find(|&&(start, end)| sp >= start && sp < end)
+        .map_or(sp, |&(_, end)| end);
+    let available = ((limit - sp) / 4) as usize;
+    let len = if available < STACK_WORDS { available } else { STACK_WORDS };
+    for i in 0..len {
+        dump.stack[i] = ptr::read_volatile((sp as *const u32).offset(i as isize));
+    }
+    dump.stack_len = len as u32;
+
+    dump.magic = MAGIC;
+    dump.checksum = dump.compute_checksum();
+}
+
+/// Returns the dump saved before the last reset, if there is one, and
+/// clears it so it is reported only once.
+pub fn take_previous() -> Option<CrashDump> {
+    unsafe {
+        let dump = ptr::read_volatile(&CRASH_DUMP);
+        CRASH_DUMP.magic = 0;
+
+        if dump.magic == MAGIC && dump.checksum == dump.compute_checksum() {
+            Some(dump)
+        } else {
+            None
+        }
+    }
+}
@@ -0,0 +1,138 @@
+//! External Watchdog Monitor
+//!
+//! The EWM drives the active-low EWM_OUT pin to reset circuitry outside the
+//! chip, independently of the core reset. It counts the 1 kHz LPO clock and
+//! must be serviced while the count is inside a window: servicing before the
+//! count passes the low compare value, or not servicing before it reaches
+//! the high compare value, asserts EWM_OUT. Optionally, the EWM_IN pin
+//! asserts EWM_OUT as well.
+//!
+//! The EWM can only be configured once after reset and cannot be stopped.
+
+use core::cell::Cell;
+use core::mem;
+use support;
+use nvic;
+use regs::ewm::*;
+
+pub static mut EWM: Ewm<'static> = Ewm::new();
+
+/// The LPO clock rate, in ticks per second.
+const LPO_HZ: u32 = 1000;
+
+/// The high compare value may be at most 0xFE.
+const MAX_COMPARE: u32 = 0xFE;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum InputPolarity {
+    /// EWM_OUT is asserted while EWM_IN is low.
+    ActiveLow,
+    /// EWM_OUT is asserted while EWM_IN is high.
+    ActiveHigh,
+}
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    /// Servicing sooner than this many milliseconds after the previous
+    /// service asserts EWM_OUT.
+    pub window_min_ms: u32,
+    /// Not servicing within this many milliseconds asserts EWM_OUT.
+    pub window_max_ms: u32,
+    /// Whether EWM_IN can also assert EWM_OUT.
+    pub input: Option<InputPolarity>,
+}
+
+pub trait Client {
+    /// Called when EWM_OUT is asserted.
+    fn asserted(&self);
+}
+
+pub struct Ewm<'a> {
+    client: Cell<Option<&'a Client>>,
+}
+
+impl<'a> Ewm<'a> {
+    pub const fn new() -> Ewm<'a> {
+        Ewm {
+            client: Cell::new(None),
+        }
+    }
+
+    fn regs(&self) -> &mut Registers {
+        unsafe { mem::transmute(EWM) }
+    }
+
+    pub fn set_client(&self, client: &'a Client) {
+        self.client.set(Some(client));
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.regs().ctrl.is_set(Control::EWMEN)
+    }
+
+    /// Starts the EWM. Returns false if it has already been started, since
+    /// its configuration cannot be changed until the next reset.
+    pub fn start(&self, config: Config) -> bool {
+        use sim::{clocks, Clock};
+
+        clocks::EWM.enable();
+        if self.enabled() {
+            return false;
+        }
+
+        // The counter is 8 bits, so divide the LPO clock down until the end
+        // of the window fits.
+        let max_ticks = config.window_max_ms * (LPO_HZ / 1000);
+        let prescaler = (max_ticks + MAX_COMPARE - 1) / MAX_COMPARE;
+        if prescaler > 256 {
+            panic!("EWM window ends too late");
+        }
+        let prescaler = if prescaler == 0 { 1 } else { prescaler };
+        let high = max_ticks / prescaler;
+        let low = config.window_min_ms * (LPO_HZ / 1000) / prescaler;
+        if low >= high {
+            panic!("EWM window must start before it ends");
+        }
+
+        let regs = self.regs();
+        regs.clkctrl.write(ClockControl::CLKSEL::Lpo);
+        regs.clkprescaler.set((prescaler - 1) as u8);
+        regs.cmpl.set(low as u8);
+        regs.cmph.set(high as u8);
+
+        let input = match config.input {
+            None => Control::INEN::CLEAR,
+            Some(InputPolarity::ActiveLow) => Control::INEN::SET + Control::ASSIN::CLEAR,
+            Some(InputPolarity::ActiveHigh) => Control::INEN::SET + Control::ASSIN::SET,
+        };
+        unsafe { nvic::enable(nvic::NvicIdx::WDOG); }
+        regs.ctrl.write(Control::INTEN::SET + input + Control::EWMEN::SET);
+        true
+    }
+
+    /// Restarts the count. EWM_OUT is asserted if this is called before the
+    /// window opens.
+    pub fn service(&self) {
+        let regs = self.regs();
+
+        // The two writes must be no more than 15 bus clocks apart.
+        unsafe {
+            support::atomic(|| {
+                regs.serv.write(Service::KEY::Key1);
+                regs.serv.write(Service::KEY::Key2);
+            });
+        }
+    }
+
+    /// The EWM shares its interrupt with the watchdog, and only interrupts
+    /// when EWM_OUT is asserted. Clearing the interrupt enable is the only
+    /// way to acknowledge it.
+    pub fn handle_interrupt(&self) {
+        let regs = self.regs();
+        if !regs.ctrl.is_set(Control::INTEN) {
+            return;
+        }
+        regs.ctrl.modify(Control::INTEN::CLEAR);
+        self.client.get().map(|client| client.asserted());
+    }
+}
@@ -0,0 +1,270 @@
+//! Floating Point Unit
+//!
+//! The kernel is built for soft float, so the FP registers only ever hold
+//! process state. Processes built for hard float get the FPU with lazy
+//! stacking: when a process that has used the FPU is interrupted, the core
+//! reserves room for s0-s15 and FPSCR in its exception frame, but only
+//! fills it in if the handler uses the FPU too.
+//!
+//! The kernel expects every process stack to end in a basic exception
+//! frame, so the exception handlers here turn an extended frame into a
+//! basic one on the way out of a process. The FP registers are left alone
+//! and stay with the process that last used them. They are only saved and
+//! reloaded when the SVC handler is about to return to a different
+//! process, which never happens while only one process uses the FPU.
+//!
+//! Processes are told apart by `mpu::process_region`.
+
+use core::ptr;
+use mpu;
+
+/// Coprocessor Access Control Register.
+const CPACR: *mut u32 = 0xE000ED88 as *mut u32;
+/// Full access to CP10 and CP11, the FPU.
+const CPACR_FPU: u32 = 0xF << 20;
+
+/// Floating Point Context Control Register.
+const FPCCR: *mut u32 = 0xE000EF34 as *mut u32;
+const FPCCR_ASPEN: u32 = 1 << 31;
+const FPCCR_LSPEN: u32 = 1 << 30;
+const FPCCR_LSPACT: u32 = 1;
+
+/// Words an extended exception frame has past the basic one: s0-s15, FPSCR
+/// and a reserved word.
+const FP_FRAME_WORDS: isize = 18;
+const BASIC_FRAME_WORDS: isize = 8;
+
+/// EXC_RETURN bits: returning to the process stack, and a basic frame.
+const EXC_RETURN_PROCESS: u32 = 1 << 2;
+const EXC_RETURN_BASIC_FRAME: u32 = 1 << 4;
+
+const MAX_CONTEXTS: usize = 8;
+
+#[derive(Copy, Clone)]
+struct FpContext {
+    /// The process, or 0 if the slot is free.
+    owner: u32,
+    regs: [u32; 32],
+    fpscr: u32,
+}
+
+const EMPTY_CONTEXT: FpContext = FpContext {
+    owner: 0,
+    regs: [0; 32],
+    fpscr: 0,
+};
+
+static mut CONTEXTS: [FpContext; MAX_CONTEXTS] = [EMPTY_CONTEXT; MAX_CONTEXTS];
+
+/// The process whose state is in the FP registers, or 0 if none.
+static mut LIVE: u32 = 0;
+
+/// Gives the FPU to processes, with automatic and lazy state preservation.
+pub unsafe fn init() {
+    ptr::write_volatile(CPACR, ptr::read_volatile(CPACR) | CPACR_FPU);
+    ptr::write_volatile(FPCCR, ptr::read_volatile(FPCCR) | FPCCR_ASPEN | FPCCR_LSPEN);
+    asm!("dsb
+          isb" :::: "volatile");
+}
+
+unsafe fn context(process: u32) -> Option<&'static mut FpContext> {
+    CONTEXTS.iter_mut().find(|context| context.owner == process)
+}
+
+unsafe fn save(process: u32) {
+    let context = match context(process) {
+        Some(context) => context,
+        None => match context(0) {
+            Some(context) => context,
+            None => panic!("Too many processes using the FPU"),
+        },
+    };
+    context.owner = process;
+
+    let fpscr: u32;
+    asm!(".fpu fpv4-sp-d16
+          vstmia $1, {s0-s31}
+          vmrs $0, fpscr"
+         : "=r"(fpscr)
+         : "r"(context.regs.as_mut_ptr())
+         : "memory"
+         : "volatile");
+    context.fpscr = fpscr;
+}
+
+unsafe fn load(context: &FpContext) {
+    asm!(".fpu fpv4-sp-d16
+          vldmia $0, {s0-s31}
+          vmsr fpscr, $1"
+         :
+         : "r"(context.regs.as_ptr()), "r"(context.fpscr)
+         : "memory"
+         : "volatile");
+}
+
+/// Called by the exception handlers on the way out of a process, with the
+/// EXC_RETURN value. If the process has used the FPU, its exception frame
+/// is an extended one, which is moved up over the FP space to leave the
+/// basic frame the kernel expects.
+#[no_mangle]
+pub unsafe extern "C" fn fpu_leave_process(exc_return: u32) {
+    if exc_return & EXC_RETURN_PROCESS == 0 || exc_return & EXC_RETURN_BASIC_FRAME != 0 {
+        return;
+    }
+
+    // The FP registers stay live, so the lazy save into the frame must
+    // never happen.
+    ptr::write_volatile(FPCCR, ptr::read_volatile(FPCCR) & !FPCCR_LSPACT);
+
+    // Moving by a multiple of 8 bytes keeps the alignment padding flagged
+    // in the stacked xPSR right.
+    let psp: *mut u32;
+    asm!("mrs $0, psp" : "=r"(psp) ::: "volatile");
+    for i in (0..BASIC_FRAME_WORDS).rev() {
+        *psp.offset(FP_FRAME_WORDS + i) = *psp.offset(i);
+    }
+    asm!("msr psp, $0" :: "r"(psp.offset(FP_FRAME_WORDS)) :: "volatile");
+
+    LIVE = mpu::process_region();
+}
+
+/// Called by the SVC handler just before it returns to a process. If the FP
+/// registers hold another process's state, it is saved and the state of
+/// this process loaded, or the registers cleared if it has none.
+#[no_mangle]
+pub unsafe extern "C" fn fpu_enter_process() {
+    let process = mpu::process_region();
+    if LIVE == process {
+        return;
+    }
+
+    if LIVE != 0 {
+        save(LIVE);
+    }
+    match context(process) {
+        Some(context) => {
+            load(context);
+            LIVE = process;
+        }
+        None => {
+            load(&EMPTY_CONTEXT);
+            LIVE = 0;
+        }
+    }
+}
+
+/// Forgets the FP state of the running process, which has faulted and
+/// will not resume where it left off.
+pub unsafe fn discard_process() {
+    let process = mpu::process_region();
+
+    ptr::write_volatile(FPCCR, ptr::read_volatile(FPCCR) & !FPCCR_LSPACT);
+    if LIVE == process {
+        LIVE = 0;
+    }
+    if let Some(context) = context(process) {
+        *context = EMPTY_CONTEXT;
+    }
+}
+
+/// Replaces the handler from the cortexm4 crate, giving processes their FP
+/// state back before returning to them.
+#[naked]
+pub unsafe extern "C" fn svc_handler() {
+    asm!("
+    cmp lr, #0xfffffff9
+    bne 1f
+
+    bl fpu_enter_process
+
+    /* Set thread mode to unprivileged */
+    mov r0, #1
+    msr CONTROL, r0
+
+    movw lr, #0xfffd
+    movt lr, #0xffff
+    bx lr
+
+  1:
+    mov r0, lr
+    bl fpu_leave_process
+
+    ldr r0, =SYSCALL_FIRED
+    mov r1, #1
+    str r1, [r0, #0]
+
+    /* Set thread mode to privileged */
+    mov r0, #0
+    msr CONTROL, r0
+
+    movw lr, #0xfff9
+    movt lr, #0xffff
+    bx lr"
+    :::: "volatile");
+}
+
+/// Replaces the handler from the cortexm4 crate, which expects a basic
+/// exception frame.
+#[naked]
+pub unsafe extern "C" fn systick_handler() {
+    asm!("
+    mov r0, lr
+    bl fpu_leave_process
+
+    /* Set thread mode to privileged */
+    mov r0, #0
+    msr CONTROL, r0
+
+    movw lr, #0xfff9
+    movt lr, #0xffff
+    bx lr"
+    :::: "volatile");
+}
+
+/// Replaces the handler from the cortexm4 crate, which expects a basic
+/// exception frame. Disables the interrupt and leaves it pending for
+/// `service_pending_interrupts`.
+#[naked]
+pub unsafe extern "C" fn generic_isr() {
+    asm!("
+    /* Skip saving process state if not coming from a process */
+    tst lr, #4
+    beq 1f
+
+    mov r0, lr
+    bl fpu_leave_process
+
+    /* The kernel's r1, stacked on the MSP, points to the process's stored
+       registers */
+    mov r1, sp
+    ldr r1, [r1, #4]
+    stmia r1, {r4-r11}
+
+    /* Set thread mode to privileged */
+    mov r0, #0
+    msr CONTROL, r0
+
+    movw lr, #0xfff9
+    movt lr, #0xffff
+
+  1:
+    /* ISRs start at 16 in IPSR */
+    mrs r0, IPSR
+    and r0, #0xff
+    sub r0, #16
+
+    /* r2 = r0 / 32, r0 = 1 << (r0 % 32) */
+    lsrs r2, r0, #5
+    and r0, r0, #31
+    mov r3, #1
+    lsl r0, r3, r0
+
+    /* Disable the interrupt in NVIC_ICER and set it pending in NVIC_ISPR */
+    ldr r3, =0xe000e180
+    str r0, [r3, r2, lsl #2]
+    ldr r3, =0xe000e200
+    str r0, [r3, r2, lsl #2]
+
+    bx lr"
+    :::: "volatile");
+}
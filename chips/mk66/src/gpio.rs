@@ -478,17 +478,39 @@ pub mod functions {
     // UART0: PB16, PB17
     pub const UART0_RX: Function<PinB16> = Function::new(Alt3);
     pub const UART0_TX: Function<PinB17> = Function::new(Alt3);
+    pub const UART0_RTS: Function<PinA17> = Function::new(Alt3);
+
+    // UART1: PC03, PC04
+    pub const UART1_RX: Function<PinC03> = Function::new(Alt3);
+    pub const UART1_TX: Function<PinC04> = Function::new(Alt3);
+    pub const UART1_RTS: Function<PinC01> = Function::new(Alt3);
+
+    // UART2: PD02, PD03
+    pub const UART2_RX: Function<PinD02> = Function::new(Alt3);
+    pub const UART2_TX: Function<PinD03> = Function::new(Alt3);
+
+    // UART4: PE24, PE25
+    pub const UART4_TX: Function<PinE24> = Function::new(Alt3);
+    pub const UART4_RX: Function<PinE25> = Function::new(Alt3);
 
     // SPI0
     pub const SPI0_MOSI: Function<PinC06> = Function::new(Alt2);
     pub const SPI0_MISO: Function<PinC07> = Function::new(Alt2);
     pub const SPI0_SCK: Function<PinA15> = Function::new(Alt2);
     pub const SPI0_CS0: Function<PinC04> = Function::new(Alt2);
+    pub const SPI0_CS4: Function<PinC00> = Function::new(Alt2);
 
     // SPI1
     pub const SPI1_MOSI: Function<PinD06> = Function::new(Alt7);
     pub const SPI1_SCK: Function<PinD05> = Function::new(Alt7);
 
+    // External Watchdog Monitor
+    pub const EWM_IN: Function<PinD04> = Function::new(Alt6);
+    pub const EWM_OUT: Function<PinD05> = Function::new(Alt6);
+
+    // Clock output
+    pub const CLKOUT: Function<PinC03> = Function::new(Alt5);
+
     // The physical i2c ports
     // In most cases there is more than one bus per i2c
     // controller. Which are used is selected on a per-board
@@ -1,13 +1,17 @@
 #![crate_name = "mk66"]
 #![crate_type = "rlib"]
-#![feature(asm,core_intrinsics,concat_idents,const_fn,const_cell_new)]
-#![no_std]
+#![feature(asm,core_intrinsics,concat_idents,const_fn,const_cell_new,naked_functions)]
+#![cfg_attr(not(test), no_std)]
 
+// Everything that only makes sense on the chip itself (the vector table,
+// fault handlers, the kernel's `Chip`) is left out of other builds, so that
+// the rest of the crate can be tested on the host with `make test`.
+#[cfg(target_arch = "arm")]
 #[allow(unused_extern_crates)]
 extern crate cortexm4;
 
 #[allow(unused_imports)]
-#[macro_use(debug, register_bitfields, register_bitmasks)]
+#[macro_use(register_bitfields, register_bitmasks)]
 extern crate kernel;
 
 #[allow(dead_code)]
@@ -17,6 +21,9 @@ extern crate sha2;
 extern crate twofish;
 extern crate block_cipher_trait;
 
+mod support;
+
+#[cfg(target_arch = "arm")]
 pub mod chip;
 pub mod nvic;
 pub mod wdog;
@@ -29,13 +36,25 @@ pub mod clock;
 pub mod pit;
 pub mod spi;
 pub mod mpu;
+pub mod crash;
+pub mod rcm;
+pub mod ewm;
+pub mod smc;
+pub mod llwu;
+#[cfg(target_arch = "arm")]
+pub mod fpu;
+pub mod lmem;
+pub mod lptmr;
+pub mod rtc;
 
 #[allow(while_true)]
 pub mod rnga;
 
-use cortexm4::{generic_isr, svc_handler, systick_handler};
+#[cfg(target_arch = "arm")]
+use fpu::{generic_isr, svc_handler, systick_handler};
 
 // TODO: Should this be moved to the cortexm crate?
+#[cfg(target_arch = "arm")]
 unsafe extern "C" fn unhandled_interrupt() {
     let mut interrupt_number: u32;
 
@@ -53,6 +72,7 @@ unsafe extern "C" fn unhandled_interrupt() {
     panic!("Unhandled Interrupt. ISR {} is active.", interrupt_number);
 }
 
+#[cfg(target_arch = "arm")]
 extern "C" {
     // _estack is not really a function, but it makes the types work.
     // You should never actually invoke it!!
@@ -61,6 +81,7 @@ extern "C" {
     // Defined by platform
     fn reset_handler();
 
+    static mut _sstack: u32;
     static mut _szero: u32;
     static mut _ezero: u32;
     static mut _etext: u32;
@@ -69,6 +90,7 @@ extern "C" {
 }
 
 // Cortex-M core interrupt vectors
+#[cfg(target_arch = "arm")]
 #[link_section=".vectors"]
 // no_mangle ensures that the symbol is kept until the final binary
 #[no_mangle]
@@ -88,13 +110,20 @@ pub static BASE_VECTORS: [unsafe extern fn(); 16] = [
     systick_handler // SysTick
 ];
 
+#[cfg(target_arch = "arm")]
 #[link_section=".vectors"]
 // no_mangle ensures that the symbol is kept until the final binary
 #[no_mangle]
 pub static IRQS: [unsafe extern "C" fn(); 100] = [generic_isr; 100];
 
+#[cfg(target_arch = "arm")]
 pub unsafe fn init() {
-    // TODO: Enable the FPU (SCB_CPACR) and LMEM_PCCCR.
+    fpu::init();
+    lmem::enable(lmem::Cache::Code);
+    lmem::enable(lmem::Cache::System);
+
+    // Allow the power modes the kernel uses before anything switches modes.
+    smc::init();
 
     // Relocate data segment.
     // Assumes data starts right after text segment as specified by the linker
@@ -123,6 +152,7 @@ pub unsafe fn init() {
 
 // TODO: This should be common to all ARM Cortex-M implementations, so I think it should be moved
 // to the cortexm crate.
+#[cfg(target_arch = "arm")]
 unsafe extern "C" fn hard_fault_handler() {
     use core::intrinsics::offset;
 
@@ -143,6 +173,8 @@ unsafe extern "C" fn hard_fault_handler() {
         );
 
     if kernel_stack {
+        crash::record(faulting_stack);
+
         let stacked_r0: u32 = *offset(faulting_stack, 0);
         let stacked_r1: u32 = *offset(faulting_stack, 1);
         let stacked_r2: u32 = *offset(faulting_stack, 2);
@@ -252,7 +284,7 @@ unsafe extern "C" fn hard_fault_handler() {
                cortexm4::ipsr_isr_number_to_str(exception_number),
                faulting_stack as u32,
                (_estack as *const ()) as u32,
-               (&_ezero as *const u32) as u32,
+               (&_sstack as *const u32) as u32,
                shcsr,
                cfsr,
                hfsr,
@@ -280,6 +312,13 @@ unsafe extern "C" fn hard_fault_handler() {
                bfarvalid,
                bfar);
     } else {
+        // The process won't resume where it faulted, so its FP state is
+        // of no use.
+        fpu::discard_process();
+
+        // Tells the process fault client once the kernel loop is back.
+        chip::process_fault();
+
         // hard fault occurred in an app, not the kernel. The app should be
         //  marked as in an error state and handled by the kernel
         asm!("ldr r0, =SYSCALL_FIRED
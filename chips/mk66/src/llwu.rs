@@ -0,0 +1,167 @@
+//! Low-Leakage Wakeup Unit
+//!
+//! In LLS and VLLS modes only the LLWU can wake the chip, from a pin routed
+//! to it or from one of a few internal modules. Waking from LLS resumes
+//! where the kernel went to sleep and raises the LLWU interrupt, which
+//! reports the wakeup source to the client. Waking from VLLS goes through
+//! reset instead: the reset status shows a low-leakage wakeup, and
+//! `recover_from_vlls` has to be called at boot to release the pins.
+
+use core::cell::Cell;
+use core::marker::PhantomData;
+use core::mem;
+use gpio::PinNum;
+use nvic;
+use regs::llwu::*;
+use regs::pmc;
+
+pub static mut LLWU: Llwu<'static> = Llwu::new();
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Edge {
+    Rising = 1,
+    Falling = 2,
+    Either = 3,
+}
+
+/// Internal modules that can wake the chip. The module's own interrupt
+/// flag must be cleared to clear the wakeup.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Module {
+    Lptmr = 0,
+    Cmp0 = 1,
+    Cmp1 = 2,
+    Cmp2 = 3,
+    Tsi = 4,
+    RtcAlarm = 5,
+    RtcSeconds = 7,
+}
+
+const MODULES: [Module; 7] = [Module::Lptmr, Module::Cmp0, Module::Cmp1, Module::Cmp2,
+                              Module::Tsi, Module::RtcAlarm, Module::RtcSeconds];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WakeupSource {
+    /// An LLWU pin, by its LLWU_P number.
+    Pin(usize),
+    Module(Module),
+}
+
+pub trait Client {
+    fn woke(&self, source: WakeupSource);
+}
+
+/// A port pin routed to the LLWU as LLWU_P`n`.
+pub struct WakeupPin<P: PinNum> {
+    _pin: PhantomData<P>,
+    pub index: usize,
+}
+
+impl<P: PinNum> WakeupPin<P> {
+    const fn new(index: usize) -> WakeupPin<P> {
+        WakeupPin {
+            _pin: PhantomData,
+            index: index,
+        }
+    }
+}
+
+pub mod pins {
+    use gpio::*;
+    use llwu::WakeupPin;
+
+    pub const LLWU_P0: WakeupPin<PinE01> = WakeupPin::new(0);
+    pub const LLWU_P1: WakeupPin<PinE02> = WakeupPin::new(1);
+    pub const LLWU_P2: WakeupPin<PinE04> = WakeupPin::new(2);
+    pub const LLWU_P3: WakeupPin<PinA04> = WakeupPin::new(3);
+    pub const LLWU_P4: WakeupPin<PinA13> = WakeupPin::new(4);
+    pub const LLWU_P5: WakeupPin<PinB00> = WakeupPin::new(5);
+    pub const LLWU_P6: WakeupPin<PinC01> = WakeupPin::new(6);
+    pub const LLWU_P7: WakeupPin<PinC03> = WakeupPin::new(7);
+    pub const LLWU_P8: WakeupPin<PinC04> = WakeupPin::new(8);
+    pub const LLWU_P9: WakeupPin<PinC05> = WakeupPin::new(9);
+    pub const LLWU_P10: WakeupPin<PinC06> = WakeupPin::new(10);
+    pub const LLWU_P11: WakeupPin<PinC11> = WakeupPin::new(11);
+    pub const LLWU_P12: WakeupPin<PinD00> = WakeupPin::new(12);
+    pub const LLWU_P13: WakeupPin<PinD02> = WakeupPin::new(13);
+    pub const LLWU_P14: WakeupPin<PinD04> = WakeupPin::new(14);
+    pub const LLWU_P15: WakeupPin<PinD06> = WakeupPin::new(15);
+}
+
+pub struct Llwu<'a> {
+    client: Cell<Option<&'a Client>>,
+}
+
+impl<'a> Llwu<'a> {
+    pub const fn new() -> Llwu<'a> {
+        Llwu {
+            client: Cell::new(None),
+        }
+    }
+
+    fn regs(&self) -> &mut Registers {
+        unsafe { mem::transmute(LLWU) }
+    }
+
+    pub fn set_client(&self, client: &'a Client) {
+        self.client.set(Some(client));
+        unsafe { nvic::enable(nvic::NvicIdx::LLWU); }
+    }
+
+    fn set_pin_edge(&self, index: usize, edge: u8) {
+        let pe = &self.regs().pe[index / 4];
+        let shift = (index % 4) * 2;
+        pe.set((pe.get() & !(0b11 << shift)) | (edge << shift));
+    }
+
+    /// Lets `pin` wake the chip from LLS and VLLS on the given edge. The pin
+    /// keeps its port configuration, including pull resistors.
+    pub fn enable_pin<P: PinNum>(&self, pin: WakeupPin<P>, edge: Edge) {
+        self.set_pin_edge(pin.index, edge as u8);
+    }
+
+    pub fn disable_pin<P: PinNum>(&self, pin: WakeupPin<P>) {
+        self.set_pin_edge(pin.index, 0);
+    }
+
+    pub fn enable_module(&self, module: Module) {
+        let me = &self.regs().me;
+        me.set(me.get() | (1 << module as u8));
+    }
+
+    pub fn disable_module(&self, module: Module) {
+        let me = &self.regs().me;
+        me.set(me.get() & !(1 << module as u8));
+    }
+
+    pub fn handle_interrupt(&self) {
+        let regs = self.regs();
+
+        for (i, pf) in regs.pf.iter().enumerate() {
+            let mut fired = pf.get();
+            pf.set(fired);
+            while fired != 0 {
+                let bit = fired.trailing_zeros() as usize;
+                fired &= !(1 << bit);
+                self.client.get().map(|client| client.woke(WakeupSource::Pin(i * 8 + bit)));
+            }
+        }
+
+        let modules = regs.mf5.get();
+        for module in MODULES.iter() {
+            if modules & (1 << *module as u8) != 0 {
+                self.client.get().map(|client| client.woke(WakeupSource::Module(*module)));
+            }
+        }
+    }
+}
+
+/// After a wakeup from VLLS, the pins stay latched in the state they had
+/// when the chip went to sleep until this is called. Call it at boot once
+/// the pins have been configured again.
+pub fn recover_from_vlls() {
+    let regs: &mut pmc::Registers = unsafe { mem::transmute(pmc::PMC) };
+    if regs.regsc.is_set(pmc::RegulatorStatusAndControl::ACKISO) {
+        regs.regsc.modify(pmc::RegulatorStatusAndControl::ACKISO::SET);
+    }
+}
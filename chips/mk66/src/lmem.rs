@@ -0,0 +1,163 @@
+//! Local Memory Controller caches
+//!
+//! The code cache sits on the core's code bus, which reaches flash and
+//! SRAM_L below 0x2000_0000. The system cache sits on the system bus, which
+//! reaches SRAM_U and everything above. Both are off after reset.
+//!
+//! The caches only see accesses made by the core, so DMA and the core can
+//! disagree about the contents of memory. Before a DMA engine reads a
+//! buffer the core wrote, call `flush_for_dma`; before the core reads a
+//! buffer a DMA engine wrote, call `invalidate_after_dma`. Flushing only
+//! matters in regions set to write-back in the region mode registers, but
+//! is cheap elsewhere.
+
+use core::mem;
+use regs::lmem::*;
+
+/// Bytes in a cache line.
+pub const LINE_SIZE: usize = 16;
+
+/// Addresses from here up go through the system cache.
+const SYSTEM_BUS_START: usize = 0x2000_0000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Cache {
+    Code,
+    System,
+}
+
+impl Cache {
+    /// The cache that holds `address`.
+    pub fn for_address(address: usize) -> Cache {
+        if address < SYSTEM_BUS_START {
+            Cache::Code
+        } else {
+            Cache::System
+        }
+    }
+
+    fn regs(&self) -> &'static mut CacheRegisters {
+        let regs: &'static mut Registers = unsafe { mem::transmute(LMEM) };
+        match *self {
+            Cache::Code => &mut regs.pc,
+            Cache::System => &mut regs.ps,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum LineCommand {
+    Invalidate = 1,
+    Push = 2,
+    /// Push, then invalidate.
+    Clear = 3,
+}
+
+/// Runs a command on both ways of the whole cache.
+fn command_all(cache: Cache, invalidate: bool, push: bool) {
+    let regs = cache.regs();
+
+    let mut command = CacheControl::GO::SET;
+    if invalidate {
+        command = command + CacheControl::INVW0::SET + CacheControl::INVW1::SET;
+    }
+    if push {
+        command = command + CacheControl::PUSHW0::SET + CacheControl::PUSHW1::SET;
+    }
+    regs.ccr.modify(command);
+    while regs.ccr.is_set(CacheControl::GO) {}
+
+    // The way bits stay set after the command completes.
+    regs.ccr.modify(CacheControl::INVW0::CLEAR + CacheControl::INVW1::CLEAR +
+                    CacheControl::PUSHW0::CLEAR + CacheControl::PUSHW1::CLEAR);
+}
+
+pub fn enabled(cache: Cache) -> bool {
+    cache.regs().ccr.is_set(CacheControl::ENCACHE)
+}
+
+/// Invalidates and enables the cache, along with its write buffer.
+pub fn enable(cache: Cache) {
+    if enabled(cache) {
+        return;
+    }
+
+    command_all(cache, true, false);
+    cache.regs().ccr.modify(CacheControl::ENWRBUF::SET + CacheControl::ENCACHE::SET);
+}
+
+/// Writes back any modified lines and disables the cache.
+pub fn disable(cache: Cache) {
+    if !enabled(cache) {
+        return;
+    }
+
+    command_all(cache, true, true);
+    cache.regs().ccr.modify(CacheControl::ENWRBUF::CLEAR + CacheControl::ENCACHE::CLEAR);
+}
+
+/// Drops every line in the cache without writing it back. Needed after
+/// flash has been programmed, for the code cache.
+pub fn invalidate_all(cache: Cache) {
+    command_all(cache, true, false);
+}
+
+/// Writes every modified line in the cache back to memory.
+pub fn flush_all(cache: Cache) {
+    command_all(cache, false, true);
+}
+
+/// Runs a line command on every line that overlaps `[start, start + len)`.
+/// Partial lines at either end are cleared, that is written back and then
+/// invalidated, so that invalidating never drops writes to the bytes next
+/// to the range.
+fn command_range(start: usize, len: usize, command: LineCommand) {
+    if len == 0 {
+        return;
+    }
+
+    let first = start & !(LINE_SIZE - 1);
+    let end = start + len;
+    let mut line = first;
+    while line < end {
+        let partial = line < start || line + LINE_SIZE > end;
+        let command = if partial && command == LineCommand::Invalidate {
+            LineCommand::Clear
+        } else {
+            command
+        };
+        command_line(Cache::for_address(line), line, command);
+        line += LINE_SIZE;
+    }
+}
+
+fn command_line(cache: Cache, address: usize, command: LineCommand) {
+    let regs = cache.regs();
+
+    regs.clcr.write(LineControl::LADSEL::PhysicalAddress + LineControl::LCMD.val(command as u32));
+    regs.csar.write(SearchAddress::PHYADDR.val((address >> 2) as u32) + SearchAddress::LGO::SET);
+    while regs.csar.is_set(SearchAddress::LGO) {}
+}
+
+/// Writes back the lines holding `[start, start + len)`.
+pub fn flush_range(start: usize, len: usize) {
+    command_range(start, len, LineCommand::Push);
+}
+
+/// Drops the lines holding `[start, start + len)`.
+pub fn invalidate_range(start: usize, len: usize) {
+    command_range(start, len, LineCommand::Invalidate);
+}
+
+/// Makes what the core wrote to `buf` visible to a DMA engine about to read
+/// it.
+pub fn flush_for_dma(buf: &[u8]) {
+    flush_range(buf.as_ptr() as usize, buf.len());
+}
+
+/// Makes what a DMA engine wrote to `buf` visible to the core. Call it
+/// once the transfer has finished, and don't touch `buf` during the
+/// transfer.
+pub fn invalidate_after_dma(buf: &mut [u8]) {
+    invalidate_range(buf.as_ptr() as usize, buf.len());
+}
@@ -0,0 +1,37 @@
+//! Low-Power Timer
+//!
+//! Only used as a free-running counter, to time one clock against another.
+//! While it counts, it can't also be used as an LLWU wakeup source.
+
+use core::mem;
+use regs::lptmr::*;
+
+pub use self::Prescale::PCS::Value as ClockSource;
+
+fn regs() -> &'static mut Registers {
+    unsafe { mem::transmute(LPTMR0) }
+}
+
+/// Starts counting every tick of `source` from zero, without a prescaler.
+pub fn start_counting(source: ClockSource) {
+    use sim::{clocks, Clock};
+    clocks::LPTMR.enable();
+
+    let regs = regs();
+    regs.csr.write(ControlStatus::TEN::CLEAR);
+    regs.psr.write(Prescale::PCS.val(source as u32) + Prescale::PBYP::SET);
+    regs.csr.write(ControlStatus::TFC::SET + ControlStatus::TEN::SET);
+}
+
+pub fn stop() {
+    regs().csr.write(ControlStatus::TEN::CLEAR);
+}
+
+/// The current count, which wraps at 16 bits.
+pub fn count() -> u16 {
+    let regs = regs();
+
+    // Writing CNR latches the counter so it can be read.
+    regs.cnr.set(0);
+    regs.cnr.get() as u16
+}
@@ -9,27 +9,55 @@ pub use self::Control1::CLKS::Value as OscSource;
 pub use self::Control1::FRDIV::Value as Frdiv;
 pub use self::Control2::RANGE::Value as OscRange;
 
+pub use self::Control2::IRCS::Value as InternalReference;
+
+/// The MCG's operating modes. Each mode is a token that can only be turned
+/// into the modes the hardware allows a direct transition to, so a
+/// sequence of transitions that compiles is one the MCG accepts.
 pub enum State {
     Fei(Fei),
-    Fee,
-    Fbi,
+    Fee(Fee),
+    Fbi(Fbi),
     Fbe(Fbe),
     Pbe(Pbe),
-    Pee,
-    Blpi,
-    Blpe,
+    Pee(Pee),
+    Blpi(Blpi),
+    Blpe(Blpe),
     Stop,
 }
 
+/// FLL engaged, referenced to the slow internal reference.
 #[derive(Copy,Clone)]
 pub struct Fei;
 
+/// FLL engaged, referenced to the external oscillator.
+#[derive(Copy,Clone)]
+pub struct Fee;
+
+/// FLL bypassed; the internal reference drives MCGOUTCLK.
+#[derive(Copy,Clone)]
+pub struct Fbi;
+
+/// FLL bypassed; the external oscillator drives MCGOUTCLK.
 #[derive(Copy,Clone)]
 pub struct Fbe;
 
+/// PLL running but bypassed; the external oscillator drives MCGOUTCLK.
 #[derive(Copy,Clone)]
 pub struct Pbe;
 
+/// PLL engaged.
+#[derive(Copy,Clone)]
+pub struct Pee;
+
+/// Like FBI with the FLL and PLL off.
+#[derive(Copy,Clone)]
+pub struct Blpi;
+
+/// Like FBE with the FLL and PLL off.
+#[derive(Copy,Clone)]
+pub struct Blpe;
+
 pub fn state() -> State {
     let mcg: &mut Registers = unsafe { mem::transmute(MCG) };
 
@@ -45,18 +73,86 @@ pub fn state() -> State {
 
     match (clks, irefs, plls, lp) {
         (OscSource::LockedLoop, true, false, _) => State::Fei(Fei),
-        (OscSource::LockedLoop, false, false, _) => State::Fee,
-        (OscSource::Internal, true, false, false) => State::Fbi,
+        (OscSource::LockedLoop, false, false, _) => State::Fee(Fee),
+        (OscSource::Internal, true, false, false) => State::Fbi(Fbi),
         (OscSource::External, false, false, false) => State::Fbe(Fbe),
-        (OscSource::LockedLoop, false, true, _) => State::Pee,
+        (OscSource::LockedLoop, false, true, _) => State::Pee(Pee),
         (OscSource::External, false, true, false) => State::Pbe(Pbe),
-        (OscSource::Internal, true, false, true) => State::Blpi,
-        (OscSource::External, false, _, true) => State::Blpe,
+        (OscSource::Internal, true, false, true) => State::Blpi(Blpi),
+        (OscSource::External, false, _, true) => State::Blpe(Blpe),
         _ => panic!("Not in a recognized power mode!")
     }
 }
 
+fn regs() -> &'static mut Registers {
+    unsafe { mem::transmute(MCG) }
+}
+
+/// Starts the crystal oscillator and waits for it to be ready.
+fn start_xtal(xtal: Xtal) {
+    let mcg = regs();
+    let Xtal { range, frdiv, load, .. } = xtal;
+
+    ::osc::enable(load);
+    mcg.c2.modify(Control2::RANGE.val(range as u8) +
+                  Control2::EREFS::SET);
+    mcg.c1.modify(Control1::FRDIV.val(frdiv as u8));
+    while !mcg.s.is_set(Status::OSCINIT0) {}
+}
+
+fn select_internal(irc: InternalReference) {
+    let mcg = regs();
+
+    // The fast IRC comes out of reset divided by two. Nothing runs from it
+    // yet, so its divider can still be changed.
+    if let InternalReference::FastInternal = irc {
+        mcg.sc.modify(StatusControl::FCRDIV::Div1);
+    }
+    mcg.c2.modify(Control2::IRCS.val(irc as u8));
+    mcg.c1.modify(Control1::CLKS::Internal + Control1::IREFS::SET);
+    while !mcg.s.matches_all(Status::CLKST::Internal + Status::IREFST::SET) {}
+}
+
+fn select_fll_internal() {
+    let mcg = regs();
+
+    mcg.c1.modify(Control1::CLKS::LockedLoop + Control1::IREFS::SET);
+    while !mcg.s.matches_all(Status::CLKST::Fll + Status::IREFST::SET) {}
+}
+
+fn select_fll_external() {
+    let mcg = regs();
+
+    mcg.c1.modify(Control1::CLKS::LockedLoop + Control1::IREFS::CLEAR);
+    while !mcg.s.matches_all(Status::CLKST::Fll + Status::IREFST::CLEAR) {}
+}
+
+fn select_external() {
+    let mcg = regs();
+
+    mcg.c1.modify(Control1::CLKS::External + Control1::IREFS::CLEAR);
+    while !mcg.s.matches_all(Status::CLKST::External + Status::IREFST::CLEAR) {}
+}
+
+fn set_low_power(lp: bool) {
+    let mcg = regs();
+
+    if lp {
+        mcg.c2.modify(Control2::LP::SET);
+    } else {
+        mcg.c2.modify(Control2::LP::CLEAR);
+    }
+}
+
+/// Keeps MCGIRCLK running, for peripherals that use the internal
+/// reference directly.
+pub fn enable_internal_reference_clock() {
+    regs().c1.modify(Control1::IRCLKEN::Active);
+}
+
 pub struct Xtal {
+    /// The crystal frequency, in Hz.
+    pub freq: u32,
     pub range: OscRange,
     pub frdiv: Frdiv,
     pub load: ::osc::OscCapacitance
@@ -68,6 +164,7 @@ pub mod xtals {
 
     #[allow(non_upper_case_globals)]
     pub const Teensy16MHz: Xtal = Xtal {
+        freq: 16_000_000,
         range: OscRange::VeryHigh,
         frdiv: Frdiv::Low16_High512,
         load: OscCapacitance::Load_10pF
@@ -77,26 +174,67 @@ pub mod xtals {
 // Source: https://branan.github.io/teensy/2017/01/28/uart.html
 impl Fei {
     pub fn use_xtal(self, xtal: Xtal) -> Fbe {
-        let mcg: &mut Registers = unsafe { mem::transmute(MCG) };
+        start_xtal(xtal);
+        select_external();
+        Fbe { }
+    }
 
-        mcg.c2.modify(Control2::RANGE.val(xtal.range as u8) +
-                      Control2::EREFS::SET);
+    pub fn use_fll_external(self, xtal: Xtal) -> Fee {
+        start_xtal(xtal);
+        select_fll_external();
+        Fee { }
+    }
 
-        mcg.c1.write(Control1::CLKS::External +
-                     Control1::FRDIV.val(xtal.frdiv as u8) +
-                     Control1::IREFS::CLEAR);
+    pub fn use_internal(self, irc: InternalReference) -> Fbi {
+        select_internal(irc);
+        Fbi { }
+    }
+}
 
-        while !mcg.s.matches_all(Status::OSCINIT0::SET +
-                             Status::IREFST::CLEAR +
-                             Status::CLKST::External) {}
+impl Fee {
+    pub fn use_fll_internal(self) -> Fei {
+        select_fll_internal();
+        Fei { }
+    }
 
+    pub fn use_external(self) -> Fbe {
+        select_external();
         Fbe { }
     }
+
+    pub fn use_internal(self, irc: InternalReference) -> Fbi {
+        select_internal(irc);
+        Fbi { }
+    }
+}
+
+impl Fbi {
+    pub fn use_fll_internal(self) -> Fei {
+        select_fll_internal();
+        Fei { }
+    }
+
+    pub fn use_fll_external(self, xtal: Xtal) -> Fee {
+        start_xtal(xtal);
+        select_fll_external();
+        Fee { }
+    }
+
+    pub fn use_xtal(self, xtal: Xtal) -> Fbe {
+        start_xtal(xtal);
+        select_external();
+        Fbe { }
+    }
+
+    pub fn enter_low_power(self) -> Blpi {
+        set_low_power(true);
+        Blpi { }
+    }
 }
 
 impl Fbe {
     pub fn enable_pll(self, multiplier: u8, divider: u8) -> Pbe {
-        let mcg: &mut Registers = unsafe { mem::transmute(MCG) };
+        let mcg = regs();
 
         if multiplier < 16 || multiplier > 47 {
             panic!("Invalid PLL VCO divide factor: {}", multiplier);
@@ -115,14 +253,83 @@ impl Fbe {
 
         Pbe { }
     }
+
+    pub fn use_fll_internal(self) -> Fei {
+        select_fll_internal();
+        Fei { }
+    }
+
+    pub fn use_fll_external(self) -> Fee {
+        select_fll_external();
+        Fee { }
+    }
+
+    pub fn use_internal(self, irc: InternalReference) -> Fbi {
+        select_internal(irc);
+        Fbi { }
+    }
+
+    pub fn enter_low_power(self) -> Blpe {
+        set_low_power(true);
+        Blpe { }
+    }
 }
 
 impl Pbe {
-    pub fn use_pll(self) {
-        let mcg: &mut Registers = unsafe { mem::transmute(MCG) };
+    pub fn use_pll(self) -> Pee {
+        let mcg = regs();
+
+        // The PLL may still be relocking after a stop mode.
+        while !mcg.s.is_set(Status::LOCK0) {}
 
         mcg.c1.modify(Control1::CLKS::LockedLoop);
 
         while !mcg.s.matches_all(Status::CLKST::Pll) {}
+
+        Pee { }
+    }
+
+    pub fn disable_pll(self) -> Fbe {
+        let mcg = regs();
+
+        mcg.c6.modify(Control6::PLLS::CLEAR);
+        while mcg.s.is_set(Status::PLLST) {}
+
+        Fbe { }
+    }
+
+    pub fn enter_low_power(self) -> Blpe {
+        set_low_power(true);
+        Blpe { }
+    }
+}
+
+impl Pee {
+    pub fn bypass_pll(self) -> Pbe {
+        select_external();
+        Pbe { }
+    }
+}
+
+impl Blpi {
+    pub fn exit_low_power(self) -> Fbi {
+        set_low_power(false);
+        Fbi { }
+    }
+}
+
+impl Blpe {
+    /// Leaves BLPE for FBE, or for PBE if the PLL was selected before
+    /// entering BLPE.
+    pub fn exit_low_power(self) -> State {
+        let mcg = regs();
+
+        set_low_power(false);
+        if mcg.c6.is_set(Control6::PLLS) {
+            while !mcg.s.matches_all(Status::PLLST::SET + Status::LOCK0::SET) {}
+            State::Pbe(Pbe { })
+        } else {
+            State::Fbe(Fbe { })
+        }
     }
 }
@@ -14,6 +14,14 @@ use kernel::common::regs::{ReadOnly, ReadWrite};
 use kernel::common::StaticRef;
 use kernel::mpu;
 
+/// Start of the first region set up for the running process. Processes
+/// never share it, so it tells them apart.
+static mut PROCESS_REGION: u32 = 0;
+
+pub fn process_region() -> u32 {
+    unsafe { PROCESS_REGION }
+}
+
 #[repr(C)]
 struct MpuErrorRegisters {
     ear: ReadOnly<u32, ErrorAddress::Register>,
@@ -275,6 +283,10 @@ impl mpu::MPU for Mpu {
         let end = attributes >> 5;
         let user = attributes & 0x7;
 
+        if region_num == 1 {
+            unsafe { PROCESS_REGION = base_address & !0x1f; }
+        }
+
         // Write to region descriptor
         regs.rgds[region_num].rgd_word0.write(RegionDescriptorWord0::SRTADDR.val(start));
         regs.rgds[region_num].rgd_word1.write(RegionDescriptorWord1::ENDADDR.val(end));
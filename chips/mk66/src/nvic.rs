@@ -14,6 +14,10 @@ struct Nvic {
     ispr: [VolatileCell<u32>; 7],
     _reserved2: [u32; 25],
     icpr: [VolatileCell<u32>; 7],
+    _reserved3: [u32; 25],
+    iabr: [VolatileCell<u32>; 7],
+    _reserved4: [u32; 57],
+    ipr: [VolatileCell<u8>; 100],
 }
 
 
@@ -256,3 +260,77 @@ pub unsafe fn clear_pending(signal: NvicIdx) {
 
     nvic.icpr[interrupt / 32].set(1 << (interrupt & 31));
 }
+
+/// Pends the interrupt, so its handler runs on the kernel's next pass
+/// through `service_pending_interrupts`, even if the device hasn't raised it.
+pub unsafe fn set_pending(signal: NvicIdx) {
+    let nvic: &mut Nvic = intrinsics::transmute(BASE_ADDRESS);
+    let interrupt = signal as usize;
+
+    nvic.ispr[interrupt / 32].set(1 << (interrupt & 31));
+}
+
+/// The K66 implements 16 priority levels, in the top four bits of each
+/// priority byte. Level 0 is the most urgent, and every interrupt starts
+/// there. Every interrupt handler only pends its interrupt for the kernel
+/// loop, so priorities just order the deferred handlers in `next_pending`.
+pub const PRIORITY_LEVELS: u8 = 16;
+const PRIORITY_SHIFT: u8 = 4;
+
+pub unsafe fn set_priority(signal: NvicIdx, priority: u8) {
+    let nvic: &mut Nvic = intrinsics::transmute(BASE_ADDRESS);
+    let interrupt = signal as usize;
+
+    if priority >= PRIORITY_LEVELS {
+        panic!("Invalid interrupt priority: {}", priority);
+    }
+    nvic.ipr[interrupt].set(priority << PRIORITY_SHIFT);
+}
+
+/// Sets every interrupt to `priority`, so that a board can rank a few
+/// interrupts above or below the rest.
+pub unsafe fn set_all_priorities(priority: u8) {
+    let nvic: &mut Nvic = intrinsics::transmute(BASE_ADDRESS);
+
+    if priority >= PRIORITY_LEVELS {
+        panic!("Invalid interrupt priority: {}", priority);
+    }
+    for ipr in nvic.ipr.iter() {
+        ipr.set(priority << PRIORITY_SHIFT);
+    }
+}
+
+pub unsafe fn priority(signal: NvicIdx) -> u8 {
+    let nvic: &mut Nvic = intrinsics::transmute(BASE_ADDRESS);
+    let interrupt = signal as usize;
+
+    nvic.ipr[interrupt].get() >> PRIORITY_SHIFT
+}
+
+/// The pending interrupt with the most urgent priority, or the lowest
+/// numbered one among equals. The kernel services interrupts in this order,
+/// so an urgent interrupt is never left waiting behind a pile of less
+/// urgent ones, though it still waits for the handler already running.
+pub unsafe fn next_pending() -> Option<u32> {
+    let nvic: &mut Nvic = intrinsics::transmute(BASE_ADDRESS);
+
+    let mut next: Option<(u8, u32)> = None;
+    for block in 0..nvic.ispr.len() {
+        let mut pending = nvic.ispr[block].get();
+        while pending != 0 {
+            let bit = pending.trailing_zeros();
+            pending &= !(1 << bit);
+
+            let interrupt = block as u32 * 32 + bit;
+            if interrupt as usize >= nvic.ipr.len() {
+                break;
+            }
+            let priority = nvic.ipr[interrupt as usize].get();
+            match next {
+                Some((most_urgent, _)) if most_urgent <= priority => {}
+                _ => next = Some((priority, interrupt)),
+            }
+        }
+    }
+    next.map(|(_, interrupt)| interrupt)
+}
@@ -3,12 +3,19 @@ use regs::osc::*;
 
 pub use self::Control::CAP::Value as OscCapacitance;
 
-pub fn enable(osc: ::mcg::Xtal) {
+pub fn enable(load: OscCapacitance) {
     let regs: &mut Registers = unsafe { mem::transmute(OSC) };
 
     // Set the capacitance.
-    regs.cr.modify(Control::CAP.val(osc.load as u8));
+    regs.cr.modify(Control::CAP.val(load as u8));
 
     // Enable the oscillator.
     regs.cr.modify(Control::EREFSTEN::SET);
 }
+
+/// Makes OSCERCLK available to peripherals.
+pub fn enable_external_reference_clock() {
+    let regs: &mut Registers = unsafe { mem::transmute(OSC) };
+
+    regs.cr.modify(Control::ERCLKEN::SET);
+}
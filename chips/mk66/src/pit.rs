@@ -1,22 +1,48 @@
+//! Periodic Interrupt Timer, used as the kernel's alarm.
+//!
+//! The PIT counts the bus clock, whose rate changes with the clock
+//! configuration. Alarm clients such as the virtual alarm mux keep
+//! deadlines in ticks, so the PIT presents a fixed `PIT_HZ` time base
+//! instead: `now` converts the chained lifetime timer from bus clocks, and
+//! a clock change carries the time base over at the old rate.
+
 use regs::pit::*;
+use core::cmp;
 use core::mem;
 use core::cell::Cell;
 use kernel::hil::time::{Client, Time, Alarm, Frequency};
 use nvic;
-use clock::peripheral_clock_hz;
+use smc;
+use clock::{self, peripheral_clock_hz};
 
 pub static mut PIT: Pit<'static> = Pit::new();
 
+/// Rate of the time base, whatever the bus clock.
+pub const PIT_HZ: u32 = 1_000_000;
+
+/// Converts `count` bus clocks at `bus_hz` into ticks of the time base.
+fn count_to_ticks(count: u64, bus_hz: u32) -> u64 {
+    let bus_hz = bus_hz as u64;
+    count / bus_hz * PIT_HZ as u64 + count % bus_hz * PIT_HZ as u64 / bus_hz
+}
+
 pub struct Pit<'a> {
     pub client: Cell<Option<&'a Client>>,
-    alarm: Cell<u32>
+    alarm: Cell<u32>,
+    running: Cell<bool>,
+    /// The time base and the lifetime count at the last clock change.
+    base_ticks: Cell<u32>,
+    base_count: Cell<u64>,
 }
 
 impl<'a> Pit<'a> {
     pub const fn new() -> Self {
         Pit {
             client: Cell::new(None),
-            alarm: Cell::new(0)
+            alarm: Cell::new(0),
+            running: Cell::new(false),
+            base_ticks: Cell::new(0),
+            base_count: Cell::new(0),
         }
     }
 
@@ -35,6 +61,24 @@ impl<'a> Pit<'a> {
         // Enable the lifetime timer.
         self.pit(1).tctrl.modify(TimerControl::TEN::SET);
         self.pit(0).tctrl.modify(TimerControl::TEN::SET);
+        self.base_ticks.set(0);
+        self.base_count.set(self.lifetime());
+        self.running.set(true);
+    }
+
+    /// Bus clocks counted by the lifetime timer. Reading the high half
+    /// latches the low half.
+    fn lifetime(&self) -> u64 {
+        let high = self.regs().ltmr64h.get() as u64;
+        let low = self.regs().ltmr64l.get() as u64;
+        ::core::u64::MAX - (high << 32 | low)
+    }
+
+    /// The time base, with the bus running at `bus_hz` since the last
+    /// clock change.
+    fn ticks_at(&self, bus_hz: u32) -> u32 {
+        let elapsed = self.lifetime().wrapping_sub(self.base_count.get());
+        self.base_ticks.get().wrapping_add(count_to_ticks(elapsed, bus_hz) as u32)
     }
 
     fn regs(&self) -> &mut Registers {
@@ -94,7 +138,7 @@ impl<'a> Pit<'a> {
 pub struct PitFrequency;
 impl Frequency for PitFrequency {
     fn frequency() -> u32 {
-        peripheral_clock_hz()
+        PIT_HZ
     }
 }
 
@@ -113,14 +157,15 @@ impl<'a> Time for Pit<'a> {
 
 impl<'a> Alarm for Pit<'a> {
     fn now(&self) -> u32 {
-        self.regs().ltmr64h.get();
-        ::core::u32::MAX - self.regs().ltmr64l.get()
+        self.ticks_at(peripheral_clock_hz())
     }
 
     fn set_alarm(&self, ticks: u32) {
         Time::disable(self);
-        self.alarm.set(ticks.wrapping_sub(self.now()));
-        self.set_counter(self.alarm.get());
+        self.alarm.set(ticks);
+        let remaining = ticks.wrapping_sub(self.now()) as u64;
+        let count = remaining * peripheral_clock_hz() as u64 / PIT_HZ as u64;
+        self.set_counter(cmp::min(count, ::core::u32::MAX as u64) as u32);
         self.enable_interrupt();
         self.enable();
     }
@@ -129,3 +174,53 @@ impl<'a> Alarm for Pit<'a> {
         self.alarm.get()
     }
 }
+
+impl<'a> smc::SleepVeto for Pit<'a> {
+    /// The PIT stops with the bus clock, so a pending alarm would be late.
+    fn deepest_sleep(&self) -> smc::SleepMode {
+        if self.is_enabled() {
+            smc::SleepMode::Wait
+        } else {
+            smc::SleepMode::VeryLowPowerStop
+        }
+    }
+}
+
+impl<'a> clock::ClockClient for Pit<'a> {
+    /// Carries the time base over at the old rate, and rescales a pending
+    /// alarm so it still fires at the same time. The lifetime timer keeps
+    /// counting, but its counts are now at the new rate.
+    fn clocks_changed(&self, previous: clock::Clocks) {
+        if !self.running.get() || previous.bus == 0 {
+            return;
+        }
+        self.base_ticks.set(self.ticks_at(previous.bus));
+        self.base_count.set(self.lifetime());
+
+        if !self.is_enabled() {
+            return;
+        }
+        let remaining = self.pit(2).cval.get() as u64;
+        let rescaled = remaining * peripheral_clock_hz() as u64 / previous.bus as u64;
+
+        self.disable();
+        self.set_counter(cmp::min(rescaled, ::core::u32::MAX as u64) as u32);
+        self.enable();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_to_ticks() {
+        assert_eq!(count_to_ticks(60_000_000, 60_000_000), 1_000_000);
+        assert_eq!(count_to_ticks(59, 60_000_000), 0);
+        // The reset FLL clock isn't a whole number of MHz; partial ticks
+        // round down.
+        assert_eq!(count_to_ticks(20_480_000 * 3600 + 30, 20_480_000), 3_600_000_001);
+        // A day at 180 MHz doesn't overflow.
+        assert_eq!(count_to_ticks(180_000_000 * 86_400, 180_000_000), 86_400_000_000);
+    }
+}
@@ -0,0 +1,118 @@
+//! Reset Control Module
+
+use core::mem;
+use regs::rcm::*;
+
+/// A reason the chip was reset. Each cause corresponds to one bit of SRS0
+/// or SRS1; more than one may be reported for the same reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ResetCause {
+    PowerOn,
+    LowVoltage,
+    Pin,
+    Watchdog,
+    LossOfLock,
+    LossOfClock,
+    /// Wakeup from a VLLS mode, which resets the chip.
+    LowLeakageWakeup,
+    Software,
+    Lockup,
+    StopAcknowledgeError,
+    EzPort,
+    DebuggerRequest,
+    Jtag,
+}
+
+/// Every cause, in the order `ResetStatus::primary` considers them.
+const CAUSES: [ResetCause; 13] = [
+    ResetCause::PowerOn,
+    ResetCause::LowVoltage,
+    ResetCause::Watchdog,
+    ResetCause::Lockup,
+    ResetCause::LossOfLock,
+    ResetCause::LossOfClock,
+    ResetCause::StopAcknowledgeError,
+    ResetCause::Software,
+    ResetCause::LowLeakageWakeup,
+    ResetCause::Pin,
+    ResetCause::EzPort,
+    ResetCause::DebuggerRequest,
+    ResetCause::Jtag,
+];
+
+impl ResetCause {
+    /// The cause's bit in the combined status, SRS1 high and SRS0 low.
+    pub fn mask(&self) -> u16 {
+        let bit = match *self {
+            ResetCause::PowerOn => 7,
+            ResetCause::Pin => 6,
+            ResetCause::Watchdog => 5,
+            ResetCause::LossOfLock => 3,
+            ResetCause::LossOfClock => 2,
+            ResetCause::LowVoltage => 1,
+            ResetCause::LowLeakageWakeup => 0,
+            ResetCause::StopAcknowledgeError => 8 + 5,
+            ResetCause::EzPort => 8 + 4,
+            ResetCause::DebuggerRequest => 8 + 3,
+            ResetCause::Software => 8 + 2,
+            ResetCause::Lockup => 8 + 1,
+            ResetCause::Jtag => 8,
+        };
+        1 << bit
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ResetCause::PowerOn => "power-on",
+            ResetCause::LowVoltage => "low voltage",
+            ResetCause::Pin => "reset pin",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::LossOfLock => "PLL loss of lock",
+            ResetCause::LossOfClock => "loss of clock",
+            ResetCause::LowLeakageWakeup => "low-leakage wakeup",
+            ResetCause::Software => "software",
+            ResetCause::Lockup => "core lockup",
+            ResetCause::StopAcknowledgeError => "stop mode acknowledge error",
+            ResetCause::EzPort => "EzPort",
+            ResetCause::DebuggerRequest => "debugger",
+            ResetCause::Jtag => "JTAG",
+        }
+    }
+}
+
+/// The causes of the most recent reset.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct ResetStatus {
+    raw: u16,
+}
+
+impl ResetStatus {
+    /// SRS1 in the high byte, SRS0 in the low byte.
+    pub fn raw(&self) -> u16 {
+        self.raw
+    }
+
+    pub fn has(&self, cause: ResetCause) -> bool {
+        self.raw & cause.mask() != 0
+    }
+
+    /// The cause that best explains the reset. A power-on reset also sets
+    /// the low-voltage bit, and a watchdog or lockup reset says more than
+    /// the pin reset that may accompany it.
+    pub fn primary(&self) -> Option<ResetCause> {
+        CAUSES.iter().cloned().find(|cause| self.has(*cause))
+    }
+
+    pub fn causes<'a>(&'a self) -> impl Iterator<Item = ResetCause> + 'a {
+        CAUSES.iter().cloned().filter(move |cause| self.has(*cause))
+    }
+}
+
+/// Returns the causes of the most recent reset. The status registers keep
+/// their value until the next reset, so this can be called at any time.
+pub fn reset_status() -> ResetStatus {
+    let regs: &mut Registers = unsafe { mem::transmute(RCM) };
+    ResetStatus {
+        raw: ((regs.srs1.get() as u16) << 8) | regs.srs0.get() as u16,
+    }
+}
@@ -0,0 +1,33 @@
+use kernel::common::regs::ReadWrite;
+
+#[repr(C)]
+pub struct Registers {
+    pub ctrl: ReadWrite<u8, Control::Register>,
+    pub serv: ReadWrite<u8, Service::Register>,
+    pub cmpl: ReadWrite<u8>,
+    pub cmph: ReadWrite<u8>,
+    pub clkctrl: ReadWrite<u8, ClockControl::Register>,
+    pub clkprescaler: ReadWrite<u8>,
+}
+
+pub const EWM: *mut Registers = 0x40061000 as *mut Registers;
+
+register_bitfields![u8,
+    Control [
+        INTEN 3,
+        INEN 2,
+        ASSIN 1,
+        EWMEN 0
+    ],
+    Service [
+        KEY OFFSET(0) NUMBITS(8) [
+            Key1 = 0xB4,
+            Key2 = 0x2C
+        ]
+    ],
+    ClockControl [
+        CLKSEL OFFSET(0) NUMBITS(2) [
+            Lpo = 0
+        ]
+    ]
+];
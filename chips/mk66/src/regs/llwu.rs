@@ -0,0 +1,28 @@
+use kernel::common::regs::{ReadWrite, ReadOnly};
+
+#[repr(C)]
+pub struct Registers {
+    /// Pin enables, four pins per register.
+    pub pe: [ReadWrite<u8>; 8],
+    pub me: ReadWrite<u8>,
+    /// Pin flags, eight pins per register. Write 1 to clear.
+    pub pf: [ReadWrite<u8>; 4],
+    pub mf5: ReadOnly<u8>,
+    pub filt1: ReadWrite<u8, PinFilter::Register>,
+    pub filt2: ReadWrite<u8, PinFilter::Register>,
+}
+
+pub const LLWU: *mut Registers = 0x4007_C000 as *mut Registers;
+
+register_bitfields![u8,
+    PinFilter [
+        FILTF OFFSET(7) NUMBITS(1) [],
+        FILTE OFFSET(5) NUMBITS(2) [
+            Disabled = 0,
+            RisingEdge = 1,
+            FallingEdge = 2,
+            AnyEdge = 3
+        ],
+        FILTSEL OFFSET(0) NUMBITS(5) []
+    ]
+];
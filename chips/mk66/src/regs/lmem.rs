@@ -0,0 +1,57 @@
+use kernel::common::regs::{ReadWrite, ReadOnly};
+
+/// The code and system caches share a register layout.
+#[repr(C)]
+pub struct CacheRegisters {
+    pub ccr: ReadWrite<u32, CacheControl::Register>,
+    pub clcr: ReadWrite<u32, LineControl::Register>,
+    pub csar: ReadWrite<u32, SearchAddress::Register>,
+    pub ccvr: ReadWrite<u32>,
+    _reserved0: [ReadOnly<u32>; 4],
+    pub rmr: ReadWrite<u32>,
+}
+
+#[repr(C)]
+pub struct Registers {
+    pub pc: CacheRegisters,
+    _reserved0: [ReadOnly<u32>; 503],
+    pub ps: CacheRegisters,
+}
+
+pub const LMEM: *mut Registers = 0xE008_2000 as *mut Registers;
+
+register_bitfields![u32,
+    CacheControl [
+        GO OFFSET(31) NUMBITS(1) [],
+        PUSHW1 OFFSET(27) NUMBITS(1) [],
+        INVW1 OFFSET(26) NUMBITS(1) [],
+        PUSHW0 OFFSET(25) NUMBITS(1) [],
+        INVW0 OFFSET(24) NUMBITS(1) [],
+        ENWRBUF OFFSET(1) NUMBITS(1) [],
+        ENCACHE OFFSET(0) NUMBITS(1) []
+    ],
+    LineControl [
+        LACC OFFSET(27) NUMBITS(1) [],
+        LADSEL OFFSET(26) NUMBITS(1) [
+            CacheAddress = 0,
+            PhysicalAddress = 1
+        ],
+        LCMD OFFSET(24) NUMBITS(2) [
+            Search = 0,
+            Invalidate = 1,
+            Push = 2,
+            Clear = 3
+        ],
+        LCWAY OFFSET(22) NUMBITS(1) [],
+        LCIMB OFFSET(21) NUMBITS(1) [],
+        LCIVB OFFSET(20) NUMBITS(1) [],
+        TDSEL OFFSET(16) NUMBITS(1) [],
+        WSEL OFFSET(14) NUMBITS(1) [],
+        CACHEADDR OFFSET(2) NUMBITS(10) [],
+        LGO OFFSET(0) NUMBITS(1) []
+    ],
+    SearchAddress [
+        PHYADDR OFFSET(2) NUMBITS(30) [],
+        LGO OFFSET(0) NUMBITS(1) []
+    ]
+];
@@ -0,0 +1,33 @@
+use kernel::common::regs::ReadWrite;
+
+#[repr(C)]
+pub struct Registers {
+    pub csr: ReadWrite<u32, ControlStatus::Register>,
+    pub psr: ReadWrite<u32, Prescale::Register>,
+    pub cmr: ReadWrite<u32>,
+    pub cnr: ReadWrite<u32>,
+}
+
+pub const LPTMR0: *mut Registers = 0x4004_0000 as *mut Registers;
+
+register_bitfields![u32,
+    ControlStatus [
+        TCF OFFSET(7) NUMBITS(1) [],
+        TIE OFFSET(6) NUMBITS(1) [],
+        TPS OFFSET(4) NUMBITS(2) [],
+        TPP OFFSET(3) NUMBITS(1) [],
+        TFC OFFSET(2) NUMBITS(1) [],
+        TMS OFFSET(1) NUMBITS(1) [],
+        TEN OFFSET(0) NUMBITS(1) []
+    ],
+    Prescale [
+        PRESCALE OFFSET(3) NUMBITS(4) [],
+        PBYP OFFSET(2) NUMBITS(1) [],
+        PCS OFFSET(0) NUMBITS(2) [
+            Mcgirclk = 0,
+            Lpo = 1,
+            Erclk32k = 2,
+            Oscerclk = 3
+        ]
+    ]
+];
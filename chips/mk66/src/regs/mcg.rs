@@ -12,7 +12,7 @@ pub struct Registers {
     pub c6: ReadWrite<u8, Control6::Register>,
     pub s: ReadOnly<u8, Status::Register>,
     _reserved0: ReadOnly<u8>,
-    pub sc: ReadWrite<u8>,
+    pub sc: ReadWrite<u8, StatusControl::Register>,
     _reserved1: ReadOnly<u8>,
     pub atcvh: ReadWrite<u8>,
     pub atcvl: ReadWrite<u8>,
@@ -33,7 +33,7 @@ register_bitfields![u8,
             Internal = 1,
             External = 2
         ],
-        FRDIV OFFSET(4) NUMBITS(2) [
+        FRDIV OFFSET(3) NUMBITS(3) [
             Low1_High32 = 0,
             Low2_High64 = 1,
             Low4_High128 = 2,
@@ -108,6 +108,18 @@ register_bitfields![u8,
         ]
     ],
 
+    StatusControl [
+        ATME OFFSET(7) NUMBITS(1) [],
+        ATMS OFFSET(6) NUMBITS(1) [],
+        ATMF OFFSET(5) NUMBITS(1) [],
+        FLTPRSRV OFFSET(4) NUMBITS(1) [],
+        FCRDIV OFFSET(1) NUMBITS(3) [
+            Div1 = 0, Div2 = 1, Div4 = 2, Div8 = 3,
+            Div16 = 4, Div32 = 5, Div64 = 6, Div128 = 7
+        ],
+        LOCS0 OFFSET(0) NUMBITS(1) []
+    ],
+
     Status [
         LOLS0 OFFSET(7) NUMBITS(1) [],
         LOCK0 OFFSET(6) NUMBITS(1) [],
@@ -5,3 +5,11 @@ pub mod uart;
 pub mod wdog;
 pub mod pit;
 pub mod spi;
+pub mod rcm;
+pub mod ewm;
+pub mod smc;
+pub mod llwu;
+pub mod pmc;
+pub mod lptmr;
+pub mod rtc;
+pub mod lmem;
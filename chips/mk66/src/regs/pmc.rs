@@ -0,0 +1,19 @@
+use kernel::common::regs::ReadWrite;
+
+#[repr(C)]
+pub struct Registers {
+    pub lvdsc1: ReadWrite<u8>,
+    pub lvdsc2: ReadWrite<u8>,
+    pub regsc: ReadWrite<u8, RegulatorStatusAndControl::Register>,
+}
+
+pub const PMC: *mut Registers = 0x4007_D000 as *mut Registers;
+
+register_bitfields![u8,
+    RegulatorStatusAndControl [
+        BGEN 4,
+        ACKISO 3,
+        REGONS 2,
+        BGBE 0
+    ]
+];
@@ -0,0 +1,36 @@
+use kernel::common::regs::{ReadOnly, ReadWrite};
+
+#[repr(C)]
+pub struct Registers {
+    pub srs0: ReadOnly<u8, SystemResetStatus0::Register>,
+    pub srs1: ReadOnly<u8, SystemResetStatus1::Register>,
+    _reserved0: [u8; 2],
+    pub rpfc: ReadWrite<u8>,
+    pub rpfw: ReadWrite<u8>,
+    pub fm: ReadWrite<u8>,
+    pub mr: ReadWrite<u8>,
+    pub ssrs0: ReadWrite<u8, SystemResetStatus0::Register>,
+    pub ssrs1: ReadWrite<u8, SystemResetStatus1::Register>,
+}
+
+pub const RCM: *mut Registers = 0x4007F000 as *mut Registers;
+
+register_bitfields![u8,
+    SystemResetStatus0 [
+        POR 7,
+        PIN 6,
+        WDOG 5,
+        LOL 3,
+        LOC 2,
+        LVD 1,
+        WAKEUP 0
+    ],
+    SystemResetStatus1 [
+        SACKERR 5,
+        EZPT 4,
+        MDM_AP 3,
+        SW 2,
+        LOCKUP 1,
+        JTAG 0
+    ]
+];
@@ -0,0 +1,26 @@
+use kernel::common::regs::ReadWrite;
+
+#[repr(C)]
+pub struct Registers {
+    pub tsr: ReadWrite<u32>,
+    pub tpr: ReadWrite<u32>,
+    pub tar: ReadWrite<u32>,
+    pub tcr: ReadWrite<u32>,
+    pub cr: ReadWrite<u32, Control::Register>,
+    pub sr: ReadWrite<u32>,
+    pub lr: ReadWrite<u32>,
+    pub ier: ReadWrite<u32>,
+}
+
+pub const RTC: *mut Registers = 0x4003_D000 as *mut Registers;
+
+register_bitfields![u32,
+    Control [
+        SC2P OFFSET(13) NUMBITS(1) [],
+        SC4P OFFSET(12) NUMBITS(1) [],
+        SC8P OFFSET(11) NUMBITS(1) [],
+        SC16P OFFSET(10) NUMBITS(1) [],
+        CLKO OFFSET(9) NUMBITS(1) [],
+        OSCE OFFSET(8) NUMBITS(1) []
+    ]
+];
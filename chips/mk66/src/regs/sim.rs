@@ -2,7 +2,7 @@ use kernel::common::regs::{ReadWrite, ReadOnly};
 
 #[repr(C)]
 pub struct Registers {
-    pub sopt2: ReadWrite<u32>,
+    pub sopt2: ReadWrite<u32, SystemOptions2::Register>,
     _reserved0: ReadWrite<u32>,
     pub sopt4: ReadWrite<u32>,
     pub sopt5: ReadWrite<u32>,
@@ -32,7 +32,29 @@ pub struct Registers {
 
 pub const SIM: *mut Registers = 0x40048004 as *mut Registers;
 
+/// SOPT1 sits apart from the rest of the SIM.
+pub const SOPT1: *mut ReadWrite<u32, SystemOptions1::Register> = 0x40047000 as *mut ReadWrite<u32, SystemOptions1::Register>;
+
 register_bitfields![u32,
+    SystemOptions1 [
+        OSC32KSEL OFFSET(18) NUMBITS(2) [
+            Osc32k = 0,
+            Rtc32k = 2,
+            Lpo1k = 3
+        ]
+    ],
+    SystemOptions2 [
+        CLKOUTSEL OFFSET(5) NUMBITS(3) [
+            FlexBus = 0,
+            Flash = 2,
+            Lpo = 3,
+            Mcgirclk = 4,
+            Rtc = 5,
+            Oscerclk = 6,
+            Irc48M = 7
+        ],
+        RTCCLKOUTSEL OFFSET(4) NUMBITS(1) []
+    ],
     SystemClockGatingControl1 [
         UART4 10,
         I2C3 7,
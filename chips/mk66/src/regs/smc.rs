@@ -1,67 +1,67 @@
-use common::regs::{ReadWrite, ReadOnly};
+use kernel::common::regs::{ReadWrite, ReadOnly};
 
-#[repr(C, packed)]
+#[repr(C)]
 pub struct Registers {
-    pub pmprot: ReadWrite<u8, PowerModeProtection>,
-    pub pmctrl: ReadWrite<u8, PowerModeControl>,
-    pub stopctrl: ReadWrite<u8, StopControl>,
-    pub pmstat: ReadOnly<u8, PowerModeStatus>
+    pub pmprot: ReadWrite<u8, PowerModeProtection::Register>,
+    pub pmctrl: ReadWrite<u8, PowerModeControl::Register>,
+    pub stopctrl: ReadWrite<u8, StopControl::Register>,
+    pub pmstat: ReadOnly<u8, PowerModeStatus::Register>,
 }
 
-bitfields! [u8,
-    PMPROT PowerModeProtection [
-        AHSRUN 7 [],
-        AVLP 5 [],
-        ALLS 3 [],
-        AVLLS 1 []
+pub const SMC: *mut Registers = 0x4007_E000 as *mut Registers;
+
+register_bitfields![u8,
+    PowerModeProtection [
+        AHSRUN 7,
+        AVLP 5,
+        ALLS 3,
+        AVLLS 1
     ],
-    PMCTRL PowerModeControl [
-        RUNM (5, Mask(0b11)) [
+    PowerModeControl [
+        RUNM OFFSET(5) NUMBITS(2) [
             NormalRun = 0,
             VeryLowPowerRun = 2,
             HighSpeedRun = 3
         ],
-        STOPA 3 [],
-        STOPM (0, Mask(0b111)) [
+        STOPA 3,
+        STOPM OFFSET(0) NUMBITS(3) [
             NormalStop = 0,
             VeryLowPowerStop = 2,
             LowLeakageStop = 3,
             VeryLowLeakageStop = 4
         ]
     ],
-    STOPCTRL StopControl [
-        PSTOPO (6, Mask(0b11)) [
-            NormalStopMode = 0,
+    StopControl [
+        PSTOPO OFFSET(6) NUMBITS(2) [
+            NormalStop = 0,
             PartialStop1 = 1,
             PartialStop2 = 2
         ],
-        PORPO 5 [
+        PORPO OFFSET(5) NUMBITS(1) [
             PORDetectEnabledInVLLS0 = 0,
             PORDetectDisabledInVLLS0 = 1
         ],
-        RAM2PO 4 [
+        RAM2PO OFFSET(4) NUMBITS(1) [
             RAM2NotPoweredInLLS2OrVLLS2 = 0,
             RAM2PoweredInLLS2AndVLLS2 = 1
         ],
-        LLSM (0, Mask(0b111)) [
+        LLSM OFFSET(0) NUMBITS(3) [
             EnterVLLS0 = 0,
             EnterVLLS1 = 1,
             EnterVLLS2OrLLS2 = 2,
             EnterVLLS3OrLLS3 = 3
         ]
     ],
-    PMSTAT PowerModeStatus [
-        PMSTAT (0, Mask(0xFF)) [
+    PowerModeStatus [
+        PMSTAT OFFSET(0) NUMBITS(8) [
             Run = 1,
-            Stop = 1<<1,
-            VLPR = 1<<2,
-            VLPW = 1<<3,
-            VLPS = 1<<4,
-            LLS = 1<<5,
-            VLLS = 1<<6,
-            HSRUN = 1<<7
+            Stop = 2,
+            VLPR = 4,
+            VLPW = 8,
+            VLPS = 16,
+            LLS = 32,
+            VLLS = 64,
+            HSRUN = 128
         ]
     ]
 ];
-
-pub const SMC_BASE: *mut Registers = 0x4007_E000 as *mut Registers;
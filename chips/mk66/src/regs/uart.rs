@@ -15,8 +15,8 @@ pub struct Registers {
     pub c4: ReadWrite<u8, Control4::Register>,
     pub c5: ReadWrite<u8, Control5::Register>,
     pub ed: ReadOnly<u8>,
-    pub modem: ReadWrite<u8>,
-    pub ir: ReadWrite<u8>, // 0x0E
+    pub modem: ReadWrite<u8, Modem::Register>,
+    pub ir: ReadWrite<u8, Infrared::Register>, // 0x0E
     _reserved0: ReadWrite<u8>,
     pub pfifo: ReadWrite<u8>, // 0x10
     pub cfifo: ReadWrite<u8>,
@@ -26,13 +26,13 @@ pub struct Registers {
     pub rwfifo: ReadWrite<u8>,
     pub rcfifo: ReadOnly<u8>, // 0x16
     _reserved1: ReadWrite<u8>,
-    pub c7816: ReadWrite<u8>, // 0x18
-    pub ie7816: ReadWrite<u8>,
-    pub is7816: ReadWrite<u8>,
-    pub wp7816: ReadWrite<u8>,
+    pub c7816: ReadWrite<u8, Control7816::Register>, // 0x18
+    pub ie7816: ReadWrite<u8, InterruptEnable7816::Register>,
+    pub is7816: ReadWrite<u8, InterruptStatus7816::Register>,
+    pub wp7816: ReadWrite<u8, WaitParameter7816::Register>,
     pub wn7816: ReadWrite<u8>,
     pub wf7816: ReadWrite<u8>,
-    pub et7816: ReadWrite<u8>,
+    pub et7816: ReadWrite<u8, ErrorThreshold7816::Register>,
     pub tl7816: ReadWrite<u8>, // 0x1F
     _reserved2: [ReadWrite<u8>; 26],
     pub ap7816a_t0: ReadWrite<u8>, // 0x3A
@@ -130,5 +130,60 @@ register_bitfields! {u8,
     Control5 [
         TDMAS 7,
         RDMAS 5
+    ],
+    Modem [
+        RXRTSE OFFSET(3) NUMBITS(1) [],
+        TXRTSPOL OFFSET(2) NUMBITS(1) [
+            ActiveLow = 0,
+            ActiveHigh = 1
+        ],
+        TXRTSE OFFSET(1) NUMBITS(1) [],
+        TXCTSE OFFSET(0) NUMBITS(1) []
+    ],
+    Infrared [
+        IREN OFFSET(2) NUMBITS(1) [],
+        TNP OFFSET(0) NUMBITS(2) [
+            ThreeSixteenths = 0,
+            OneSixteenth = 1,
+            OneThirtySecond = 2,
+            OneQuarter = 3
+        ]
+    ],
+    Control7816 [
+        ONACK OFFSET(4) NUMBITS(1) [],
+        ANACK OFFSET(3) NUMBITS(1) [],
+        INIT OFFSET(2) NUMBITS(1) [],
+        TTYPE OFFSET(1) NUMBITS(1) [
+            T0 = 0,
+            T1 = 1
+        ],
+        ISO_7816E OFFSET(0) NUMBITS(1) []
+    ],
+    InterruptEnable7816 [
+        WTE 7,
+        CWTE 6,
+        BWTE 5,
+        INITDE 4,
+        GTVE 2,
+        TXTE 1,
+        RXTE 0
+    ],
+    InterruptStatus7816 [
+        WT 7,
+        CWT 6,
+        BWT 5,
+        INITD 4,
+        GTV 2,
+        TXT 1,
+        RXT 0
+    ],
+    WaitParameter7816 [
+        WI OFFSET(0) NUMBITS(8) [],
+        CWI OFFSET(4) NUMBITS(4) [],
+        BWI OFFSET(0) NUMBITS(4) []
+    ],
+    ErrorThreshold7816 [
+        TXTHRESHOLD OFFSET(4) NUMBITS(4) [],
+        RXTHRESHOLD OFFSET(0) NUMBITS(4) []
     ]
 }
@@ -13,7 +13,7 @@ pub struct Registers {
     pub tmrouth: ReadWrite<u16>,
     pub tmroutl: ReadWrite<u16>,
     pub rstcnt:  ReadWrite<u16>,
-    pub presc:   ReadWrite<u16>,
+    pub presc:   ReadWrite<u16, Prescaler::Register>,
 }
 
 pub const WDOG: *mut Registers = 0x40052000 as *mut Registers;
@@ -29,6 +29,9 @@ register_bitfields![u16,
         CLKSRC 1,
         WDOGEN 0
     ],
+    Prescaler [
+        PRESCVAL OFFSET(8) NUMBITS(3) []
+    ],
     Refresh [
         KEY OFFSET(0) NUMBITS(16) [
             Key1 = 0xA602,
@@ -0,0 +1,29 @@
+//! Real Time Clock
+//!
+//! Only the 32.768 kHz oscillator is supported so far. It runs from VBAT,
+//! so it keeps running across resets once started.
+
+use core::mem;
+use regs::rtc::*;
+
+fn regs() -> &'static mut Registers {
+    unsafe { mem::transmute(RTC) }
+}
+
+pub fn oscillator_enabled() -> bool {
+    use sim::{clocks, Clock};
+    clocks::RTC.enable();
+
+    regs().cr.is_set(Control::OSCE)
+}
+
+/// Starts the 32.768 kHz oscillator with the 20 pF load the Teensy crystal
+/// needs. The oscillator takes up to a second to stabilize after it is
+/// first enabled.
+pub fn enable_oscillator() {
+    if oscillator_enabled() {
+        return;
+    }
+
+    regs().cr.modify(Control::SC16P::SET + Control::SC4P::SET + Control::OSCE::SET);
+}
@@ -2,7 +2,10 @@
 
 use core::mem;
 use regs::sim::*;
-use kernel::common::regs::FieldValue;
+use kernel::common::regs::{FieldValue, ReadWrite};
+
+pub use self::SystemOptions2::CLKOUTSEL::Value as ClkOut;
+pub use self::SystemOptions1::OSC32KSEL::Value as Erclk32k;
 
 pub type Clock1 = FieldValue<u32, SystemClockGatingControl1::Register>;
 pub type Clock2 = FieldValue<u32, SystemClockGatingControl2::Register>;
@@ -137,11 +140,25 @@ pub mod clocks {
     pub const FLEXBUS: Clock7 = SystemClockGatingControl7::FLEXBUS::SET;
 }
 
-pub fn set_dividers(core: u32, bus: u32, flash: u32) {
+pub fn set_dividers(core: u32, bus: u32, flexbus: u32, flash: u32) {
     let regs: &mut Registers = unsafe { mem::transmute(SIM) };
 
     regs.clkdiv1.modify(ClockDivider1::Core.val(core - 1) +
                         ClockDivider1::Bus.val(bus - 1) +
-                        ClockDivider1::FlexBus.val(bus - 1) +
+                        ClockDivider1::FlexBus.val(flexbus - 1) +
                         ClockDivider1::Flash.val(flash - 1));
 }
+
+/// Selects the clock driven onto the CLKOUT pin.
+pub fn set_clkout(source: ClkOut) {
+    let regs: &mut Registers = unsafe { mem::transmute(SIM) };
+
+    regs.sopt2.modify(SystemOptions2::CLKOUTSEL.val(source as u32));
+}
+
+/// Selects the 32 kHz clock used by the LPTMR, among others.
+pub fn set_erclk32k(source: Erclk32k) {
+    let sopt1: &mut ReadWrite<u32, SystemOptions1::Register> = unsafe { mem::transmute(SOPT1) };
+
+    sopt1.modify(SystemOptions1::OSC32KSEL.val(source as u32));
+}
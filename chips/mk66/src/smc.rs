@@ -0,0 +1,244 @@
+//! System Mode Controller
+//!
+//! Puts the chip to sleep when the kernel is idle. WAIT only stops the core
+//! clock, so every peripheral keeps running. STOP and VLPS also stop the
+//! system and bus clocks, which saves far more power but stops most
+//! peripherals; only asynchronous wakeups such as pin interrupts still
+//! work. Drivers for peripherals that would lose work in a stop mode veto
+//! it, and the chip sleeps as deeply as every driver allows.
+
+use core::mem;
+use core::ptr;
+use mcg;
+use support;
+use regs::smc::*;
+
+/// System Control Register, for SLEEPDEEP.
+const SCB_SCR: *mut u32 = 0xE000ED10 as *mut u32;
+const SCR_SLEEPDEEP: u32 = 1 << 2;
+
+/// Sleep modes, from shallowest to deepest.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SleepMode {
+    /// Core clock stopped; peripherals keep running.
+    Wait,
+    /// System and bus clocks stopped.
+    Stop,
+    /// Like STOP, with the regulator in low-power mode. Takes longer to
+    /// wake up from.
+    VeryLowPowerStop,
+    /// Most of the chip is powered down, and only the LLWU can wake it.
+    /// Only used once allowed with `allow_low_leakage_stop`.
+    LowLeakageStop,
+}
+
+/// Very low leakage stop modes, from most to least retained. The chip
+/// resets when it wakes from any of them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VeryLowLeakageMode {
+    /// All RAM is retained.
+    Vlls3,
+    /// Only the upper 32 KiB of SRAM_U is retained.
+    Vlls2,
+    /// Only the VBAT and system register files are retained.
+    Vlls1,
+    /// Like VLLS1, with the 1 kHz LPO off as well.
+    Vlls0,
+}
+
+/// Implemented by drivers whose peripherals stop running in stop modes.
+pub trait SleepVeto {
+    /// The deepest mode the peripheral can sleep through right now.
+    fn deepest_sleep(&self) -> SleepMode;
+}
+
+/// Vetoes held by drivers outside the chip crate.
+static mut DEEP_SLEEP_VETOES: usize = 0;
+
+static mut LOW_LEAKAGE_ALLOWED: bool = false;
+
+/// Lets the kernel sleep in LLS when nothing vetoes deep sleep. Only
+/// wakeup sources enabled in the LLWU wake the chip from LLS, so enable
+/// every source the board needs first.
+pub fn allow_low_leakage_stop(allow: bool) {
+    unsafe {
+        LOW_LEAKAGE_ALLOWED = allow;
+    }
+}
+
+/// Keeps the chip out of stop modes until `allow_deep_sleep` is called.
+/// Calls nest.
+pub fn prevent_deep_sleep() {
+    unsafe {
+        DEEP_SLEEP_VETOES += 1;
+    }
+}
+
+pub fn allow_deep_sleep() {
+    unsafe {
+        if DEEP_SLEEP_VETOES == 0 {
+            panic!("allow_deep_sleep without prevent_deep_sleep");
+        }
+        DEEP_SLEEP_VETOES -= 1;
+    }
+}
+
+/// The deepest mode allowed by drivers outside the chip crate.
+pub fn deepest_sleep() -> SleepMode {
+    unsafe {
+        if DEEP_SLEEP_VETOES > 0 {
+            SleepMode::Wait
+        } else if LOW_LEAKAGE_ALLOWED {
+            SleepMode::LowLeakageStop
+        } else {
+            SleepMode::VeryLowPowerStop
+        }
+    }
+}
+
+fn regs() -> &'static mut Registers {
+    unsafe { mem::transmute(SMC) }
+}
+
+/// Allows the chip to enter the power modes the kernel uses. PMPROT can
+/// only be written once after reset, so every mode has to be allowed here.
+pub fn init() {
+    regs().pmprot.write(PowerModeProtection::AHSRUN::SET +
+                        PowerModeProtection::AVLP::SET +
+                        PowerModeProtection::ALLS::SET +
+                        PowerModeProtection::AVLLS::SET);
+}
+
+/// Switches to HSRUN, which allows a core clock above 120 MHz. Must be
+/// called before raising the clocks past the normal RUN limits.
+pub fn enter_high_speed_run() {
+    let regs = regs();
+    regs.pmctrl.modify(PowerModeControl::RUNM::HighSpeedRun);
+    while !regs.pmstat.matches_all(PowerModeStatus::PMSTAT::HSRUN) {}
+}
+
+/// Switches back from HSRUN to RUN. The clocks must already be within the
+/// RUN limits.
+pub fn exit_high_speed_run() {
+    let regs = regs();
+    regs.pmctrl.modify(PowerModeControl::RUNM::NormalRun);
+    while !regs.pmstat.matches_all(PowerModeStatus::PMSTAT::Run) {}
+}
+
+pub fn high_speed_run() -> bool {
+    regs().pmstat.matches_all(PowerModeStatus::PMSTAT::HSRUN)
+}
+
+/// Switches to VLPR. The clocks must already be within the VLPR limits:
+/// 4 MHz core, bus and FlexBus, and 1 MHz flash.
+pub fn enter_very_low_power_run() {
+    let regs = regs();
+    regs.pmctrl.modify(PowerModeControl::RUNM::VeryLowPowerRun);
+    while !regs.pmstat.matches_all(PowerModeStatus::PMSTAT::VLPR) {}
+}
+
+/// Switches back from VLPR to RUN. The MCG can't change modes until this
+/// has been done.
+pub fn exit_very_low_power_run() {
+    let regs = regs();
+    regs.pmctrl.modify(PowerModeControl::RUNM::NormalRun);
+    while !regs.pmstat.matches_all(PowerModeStatus::PMSTAT::Run) {}
+}
+
+pub fn very_low_power_run() -> bool {
+    regs().pmstat.matches_all(PowerModeStatus::PMSTAT::VLPR)
+}
+
+/// Flash can only be programmed or erased in RUN.
+pub fn flash_programming_allowed() -> bool {
+    regs().pmstat.matches_all(PowerModeStatus::PMSTAT::Run)
+}
+
+/// Sleeps in `mode` until an interrupt is pending. Must be called with
+/// interrupts disabled, so the interrupt is handled only once the clocks
+/// are restored. Returns at once in HSRUN, which can't sleep at all.
+pub unsafe fn sleep(mode: SleepMode) {
+    let regs = regs();
+
+    // HSRUN can only switch to RUN, which would mean slowing the clocks
+    // down first, so the kernel loop spins instead. VLPR can only go to the
+    // very low power and low leakage stop modes. WAIT from VLPR is VLPW.
+    if high_speed_run() {
+        return;
+    }
+    let mode = if very_low_power_run() && mode == SleepMode::Stop {
+        SleepMode::VeryLowPowerStop
+    } else {
+        mode
+    };
+
+    match mode {
+        SleepMode::Wait => {}
+        SleepMode::Stop => regs.pmctrl.modify(PowerModeControl::STOPM::NormalStop),
+        SleepMode::VeryLowPowerStop => regs.pmctrl.modify(PowerModeControl::STOPM::VeryLowPowerStop),
+        SleepMode::LowLeakageStop => {
+            regs.stopctrl.modify(StopControl::LLSM::EnterVLLS3OrLLS3);
+            regs.pmctrl.modify(PowerModeControl::STOPM::LowLeakageStop);
+        }
+    }
+    let deep = mode != SleepMode::Wait;
+    if deep {
+        // Read back so the mode is set before the core sleeps.
+        let _ = regs.pmctrl.get();
+    }
+    set_sleepdeep(deep);
+
+    support::wfi();
+
+    if deep {
+        set_sleepdeep(false);
+
+        restore_clocks();
+    }
+}
+
+/// The PLL is turned off in stop modes, and the MCG wakes up in PBE mode.
+/// Switches back to the PLL once it has locked again.
+fn restore_clocks() {
+    if let mcg::State::Pbe(pbe) = mcg::state() {
+        pbe.use_pll();
+    }
+}
+
+/// Powers the chip down until an LLWU wakeup source fires, which resets the
+/// chip. Returns only if a pending interrupt keeps the chip from entering
+/// the mode.
+pub unsafe fn power_down(mode: VeryLowLeakageMode) {
+    let regs = regs();
+
+    let llsm = match mode {
+        VeryLowLeakageMode::Vlls3 => StopControl::LLSM::EnterVLLS3OrLLS3,
+        VeryLowLeakageMode::Vlls2 => StopControl::LLSM::EnterVLLS2OrLLS2,
+        VeryLowLeakageMode::Vlls1 => StopControl::LLSM::EnterVLLS1,
+        VeryLowLeakageMode::Vlls0 => StopControl::LLSM::EnterVLLS0,
+    };
+    regs.stopctrl.modify(llsm);
+    regs.pmctrl.modify(PowerModeControl::STOPM::VeryLowLeakageStop);
+    let _ = regs.pmctrl.get();
+    set_sleepdeep(true);
+
+    support::wfi();
+
+    set_sleepdeep(false);
+    restore_clocks();
+}
+
+unsafe fn set_sleepdeep(deep: bool) {
+    let scr = ptr::read_volatile(SCB_SCR);
+    if deep {
+        ptr::write_volatile(SCB_SCR, scr | SCR_SLEEPDEEP);
+    } else {
+        ptr::write_volatile(SCB_SCR, scr & !SCR_SLEEPDEEP);
+    }
+}
+
+/// True if the last attempt to enter a stop mode was aborted by an
+/// interrupt.
+pub fn stop_aborted() -> bool {
+    regs().pmctrl.is_set(PowerModeControl::STOPA)
+}
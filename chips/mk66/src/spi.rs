@@ -3,8 +3,10 @@ use kernel::hil::spi::*;
 use kernel::ReturnCode;
 use kernel::common::cells::TakeCell;
 use core::cell::Cell;
+use core::cmp;
 use core::mem;
 use clock;
+use smc;
 use nvic::{self, NvicIdx};
 
 pub enum SpiRole {
@@ -20,6 +22,9 @@ pub struct Spi<'a> {
     write: TakeCell<'static, [u8]>,
     read: TakeCell<'static, [u8]>,
     transfer_len: Cell<usize>,
+    requested_baud_rate: Cell<u32>,
+    /// Set while the bus clock only allows a faster SPI clock than before
+    paused: Cell<bool>,
 }
 
 pub static mut SPI0: Spi<'static> = Spi::new(0);
@@ -41,6 +46,8 @@ impl<'a> Spi<'a> {
             write: TakeCell::empty(),
             read: TakeCell::empty(),
             transfer_len: Cell::new(0),
+            requested_baud_rate: Cell::new(0),
+            paused: Cell::new(false),
         }
     }
 
@@ -239,6 +246,7 @@ impl<'a> Spi<'a> {
                                  ClockAndTransferAttributes::BR.val(scaler as u32));
         self.resume();
 
+        self.requested_baud_rate.set(rate);
         Spi::baud_rate(dbls[dbl], prescalers[prescaler], scalers[scaler])
     }
 
@@ -361,6 +369,10 @@ impl<'a> SpiMaster for Spi<'a> {
                         len: usize)
                         -> ReturnCode {
 
+        if self.paused.get() {
+            return ReturnCode::EOFF;
+        }
+
         self.start_of_queue();
         if let Some(rbuf) = read_buffer {
             for i in 0..len {
@@ -481,3 +493,41 @@ impl<'a> SpiMaster for Spi<'a> {
         self.regs().pushr_cmd.modify(TxFifoPushCommand::CONT::ChipSelectAssertedBetweenTxfers);
     }
 }
+
+impl<'a> smc::SleepVeto for Spi<'a> {
+    fn deepest_sleep(&self) -> smc::SleepMode {
+        if self.write.is_some() {
+            smc::SleepMode::Wait
+        } else {
+            smc::SleepMode::VeryLowPowerStop
+        }
+    }
+}
+
+impl<'a> clock::ClockClient for Spi<'a> {
+    /// Picks the closest rate to the requested one from the new bus clock.
+    /// Devices can take a slower clock but not a faster one, so if that
+    /// rate is faster than both the requested rate and the rate before the
+    /// change, the SPI is disabled and transfers fail with `EOFF` until a
+    /// later clock change makes a safe rate reachable again.
+    fn clocks_changed(&self, previous: clock::Clocks) {
+        let rate = self.requested_baud_rate.get();
+        if rate == 0 {
+            return;
+        }
+
+        // The dividers haven't changed yet, so scale what they produce now
+        // back to the old bus clock.
+        let before = (self.get_baud_rate() as u64 * previous.bus as u64 /
+                      clock::bus_clock_hz() as u64) as u32;
+
+        let after = self.set_baud_rate(rate);
+        if after > cmp::max(rate, before) {
+            self.paused.set(true);
+            self.disable();
+        } else if self.paused.get() {
+            self.paused.set(false);
+            self.enable();
+        }
+    }
+}
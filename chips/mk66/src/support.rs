@@ -0,0 +1,31 @@
+//! Core instructions the drivers need. Off the target they do nothing, so
+//! that the crate also builds for its host tests.
+
+#[cfg(target_arch = "arm")]
+pub use cortexm4::support::atomic;
+
+#[cfg(not(target_arch = "arm"))]
+pub unsafe fn atomic<F, R>(f: F) -> R
+where
+    F: FnOnce() -> R,
+{
+    f()
+}
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub unsafe fn nop() {
+    asm!("nop" :::: "volatile");
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub unsafe fn nop() {}
+
+#[cfg(target_arch = "arm")]
+#[inline(always)]
+pub unsafe fn wfi() {
+    asm!("wfi" :::: "volatile");
+}
+
+#[cfg(not(target_arch = "arm"))]
+pub unsafe fn wfi() {}
@@ -4,18 +4,252 @@ use core::cell::Cell;
 use kernel::common::cells::TakeCell;
 use kernel::hil;
 use kernel::hil::uart;
+use kernel::ReturnCode;
+use core::cmp;
 use core::mem;
 use nvic;
 use regs::uart::*;
 use clock;
+use smc;
 
 pub struct Uart {
     index: usize,
     registers: *mut Registers,
     client: Cell<Option<&'static uart::Client>>,
     buffer: TakeCell<'static, [u8]>,
+    tx_buffer: TakeCell<'static, [u8]>,
+    /// A buffer passed to `transmit` while busy, to be handed back
+    tx_rejected: TakeCell<'static, [u8]>,
+    tx_len: Cell<usize>,
+    tx_index: Cell<usize>,
     rx_len: Cell<usize>,
-    rx_index: Cell<usize>
+    rx_index: Cell<usize>,
+    rx_idle_complete: Cell<bool>,
+    rx_idle_pending: Cell<bool>,
+    rx_complete_pending: Cell<bool>,
+    ring: TakeCell<'static, [u8]>,
+    ring_head: Cell<usize>,
+    ring_len: Cell<usize>,
+    ring_dropped: Cell<usize>,
+    iso7816_client: Cell<Option<&'static Iso7816Client>>,
+    options: Cell<UartOptions>,
+    parity: Cell<hil::uart::Parity>,
+    requested_baud_rate: Cell<u32>,
+    /// TE and RE as they were before a clock change left the baud rate
+    /// unreachable
+    paused: Cell<Option<(u8, u8)>>,
+}
+
+/// Largest acceptable deviation from a requested baud rate, in parts per
+/// thousand. Receivers typically tolerate 2-3% total mismatch, split between
+/// both ends of the link.
+pub const BAUD_RATE_TOLERANCE: u32 = 15;
+
+/// Baud rate generator settings: `baud = clock / (16 * (SBR + BRFA / 32))`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BaudDivisor {
+    /// 13-bit integer divisor
+    pub sbr: u16,
+    /// 5-bit fractional adjust, in 1/32 increments
+    pub brfa: u8,
+    /// The baud rate these settings actually produce
+    pub baud_rate: u32,
+}
+
+/// Computes the closest baud rate generator settings for `baud_rate` given a
+/// UART module clock of `uart_clock` Hz. Fails with `EINVAL` if the divisor is
+/// out of range or the result is not within `BAUD_RATE_TOLERANCE`.
+pub fn baud_divisor(uart_clock: u32, baud_rate: u32) -> Result<BaudDivisor, ReturnCode> {
+    if baud_rate == 0 {
+        return Err(ReturnCode::EINVAL);
+    }
+
+    // Total divisor in units of 1/32: (clock / (16 * baud)) * 32, rounded.
+    let divisor = (uart_clock * 2 + baud_rate / 2) / baud_rate;
+
+    let sbr = divisor >> 5;
+    let brfa = divisor & 0x1F;
+    if sbr < 1 || sbr > 0x1FFF {
+        return Err(ReturnCode::EINVAL);
+    }
+
+    let actual = self::baud_rate(uart_clock, sbr as u16, brfa as u8);
+    let error = if actual > baud_rate { actual - baud_rate } else { baud_rate - actual };
+    if (error as u64) * 1000 > (baud_rate as u64) * (BAUD_RATE_TOLERANCE as u64) {
+        return Err(ReturnCode::EINVAL);
+    }
+
+    Ok(BaudDivisor {
+        sbr: sbr as u16,
+        brfa: brfa as u8,
+        baud_rate: actual,
+    })
+}
+
+/// The baud rate produced by the given generator settings.
+pub fn baud_rate(uart_clock: u32, sbr: u16, brfa: u8) -> u32 {
+    let divisor = ((sbr as u32) << 5) | (brfa as u32 & 0x1F);
+    if divisor == 0 {
+        return 0;
+    }
+    (uart_clock * 2) / divisor
+}
+
+/// With nine data bits, each character takes two bytes in the transmit and
+/// receive buffers: the low eight bits, then a byte holding the ninth bit.
+/// Buffer lengths are rounded down to whole characters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DataBits {
+    Eight,
+    /// The ninth bit marks address characters on multidrop buses.
+    Nine,
+}
+
+impl DataBits {
+    /// Bytes each character takes in a buffer.
+    pub fn width(&self) -> usize {
+        match *self {
+            DataBits::Eight => 1,
+            DataBits::Nine => 2,
+        }
+    }
+}
+
+/// Width of the transmitted IrDA pulse, as a fraction of a bit time.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum IrdaPulseWidth {
+    ThreeSixteenths,
+    OneSixteenth,
+    OneThirtySecond,
+    OneQuarter,
+}
+
+/// Node addresses for nine bit multidrop buses. Address characters that
+/// match neither are discarded by the receiver. Only usable with
+/// `DataBits::Nine`, whose ninth bit marks address characters.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AddressMatch {
+    pub ma1: Option<u8>,
+    pub ma2: Option<u8>,
+}
+
+impl AddressMatch {
+    pub fn enabled(&self) -> bool {
+        self.ma1.is_some() || self.ma2.is_some()
+    }
+}
+
+impl UartOptions {
+    /// Address matching needs the ninth bit to tell addresses from data.
+    fn valid(&self) -> bool {
+        !self.address_match.enabled() || self.data_bits == DataBits::Nine
+    }
+}
+
+/// Polarity of the RS-485 transmitter enable signal driven on RTS.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TransmitEnablePolarity {
+    ActiveLow,
+    ActiveHigh,
+}
+
+/// UART options beyond those in `hil::uart::UARTParams`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct UartOptions {
+    pub data_bits: DataBits,
+    /// IrDA pulse encoding, if enabled
+    pub irda: Option<IrdaPulseWidth>,
+    pub address_match: AddressMatch,
+    /// RS-485 mode: RTS is asserted from one bit time before the first start
+    /// bit until the last stop bit of a transmission has been sent.
+    pub rs485: Option<TransmitEnablePolarity>,
+}
+
+impl Default for UartOptions {
+    fn default() -> UartOptions {
+        UartOptions {
+            data_bits: DataBits::Eight,
+            irda: None,
+            address_match: AddressMatch { ma1: None, ma2: None },
+            rs485: None,
+        }
+    }
+}
+
+/// Transmission protocol used in ISO-7816 mode.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Iso7816Protocol {
+    /// Character oriented, half duplex
+    T0,
+    /// Block oriented, half duplex
+    T1,
+}
+
+/// ISO-7816 timing and error handling parameters, in ETUs where applicable.
+#[derive(Copy, Clone, Debug)]
+pub struct Iso7816Params {
+    pub protocol: Iso7816Protocol,
+    /// Extra guard time N between transmitted characters
+    pub guard_time: u8,
+    /// T=0 work waiting time integer (WI)
+    pub wait_time_integer: u8,
+    /// T=1 character waiting time integer (CWI)
+    pub cwi: u8,
+    /// T=1 block waiting time integer (BWI)
+    pub bwi: u8,
+    /// Wait time multiplier (WF)
+    pub wait_time_multiplier: u8,
+    /// T=0 NACKs tolerated per character before a retries exceeded event
+    pub retries: u8,
+}
+
+impl Default for Iso7816Params {
+    /// The defaults specified by ISO-7816-3 before any ATR parameters apply.
+    fn default() -> Iso7816Params {
+        Iso7816Params {
+            protocol: Iso7816Protocol::T0,
+            guard_time: 0,
+            wait_time_integer: 10,
+            cwi: 13,
+            bwi: 4,
+            wait_time_multiplier: 1,
+            retries: 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Iso7816Event {
+    /// The TS character was received; `inverse` is true for the inverse
+    /// convention.
+    InitialCharacter { inverse: bool },
+    /// The card was silent for longer than the waiting time.
+    WaitTimeout,
+    /// A character was NACKed by the card more than the allowed number of
+    /// times.
+    TransmitRetriesExceeded,
+    /// A received character was NACKed more than the allowed number of times.
+    ReceiveRetriesExceeded,
+    /// The card sent a character before the guard time elapsed.
+    GuardTimeViolation,
+}
+
+pub trait Iso7816Client {
+    fn iso7816_event(&self, event: Iso7816Event);
+}
+
+/// Extends `hil::uart::UART` with the frame options `UARTParams` can't
+/// express, for capsules that need them.
+pub trait UartExtended: hil::uart::UART {
+    /// Configures and enables the UART, returning the baud rate actually
+    /// achieved. Fails with `EINVAL`, leaving the transmitter and receiver
+    /// disabled, if the requested rate cannot be generated within
+    /// `BAUD_RATE_TOLERANCE` of the current UART clock. Also fails with
+    /// `EINVAL`, without touching the UART, if `options` asks for address
+    /// matching with eight data bits.
+    fn configure(&self, params: uart::UARTParams, options: UartOptions) -> Result<u32, ReturnCode>;
+
+    fn options(&self) -> UartOptions;
 }
 
 pub static mut UART0: Uart = Uart::new(0);
@@ -31,37 +265,424 @@ impl Uart {
             registers: UART_BASE_ADDRS[index],
             client: Cell::new(None),
             buffer: TakeCell::empty(),
+            tx_buffer: TakeCell::empty(),
+            tx_rejected: TakeCell::empty(),
+            tx_len: Cell::new(0),
+            tx_index: Cell::new(0),
             rx_len: Cell::new(0),
             rx_index: Cell::new(0),
+            rx_idle_complete: Cell::new(false),
+            rx_idle_pending: Cell::new(false),
+            rx_complete_pending: Cell::new(false),
+            ring: TakeCell::empty(),
+            ring_head: Cell::new(0),
+            ring_len: Cell::new(0),
+            ring_dropped: Cell::new(0),
+            iso7816_client: Cell::new(None),
+            options: Cell::new(UartOptions {
+                data_bits: DataBits::Eight,
+                irda: None,
+                address_match: AddressMatch { ma1: None, ma2: None },
+                rs485: None,
+            }),
+            parity: Cell::new(hil::uart::Parity::None),
+            requested_baud_rate: Cell::new(0),
+            paused: Cell::new(None),
         }
     }
 
     pub fn handle_interrupt(&self) {
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        if regs.c7816.is_set(Control7816::ISO_7816E) {
+            self.handle_iso7816_interrupt();
+        }
+
+        // The edge that woke the chip from a stop mode; see `deepest_sleep`.
+        if regs.bdh.is_set(BaudRateHigh::RXEDGIE) && regs.s2.is_set(Status2::RXEDGIF) {
+            regs.bdh.modify(BaudRateHigh::RXEDGIE::CLEAR);
+            regs.s2.modify(Status2::RXEDGIF::SET);
+        }
+
+        // A receive that could complete from buffered bytes alone, before
+        // any new byte is taken.
+        if self.rx_complete_pending.get() {
+            self.complete_receive(self.rx_index.get());
+        }
+
+        self.tx_rejected.take().map(|buf| {
+            self.client.get().map(move |client| {
+                client.transmit_complete(buf, uart::Error::RepeatCallError)
+            });
+        });
+
+        if regs.c2.is_set(Control2::TIE) && regs.s1.is_set(Status1::TRDE) {
+            self.transmit_next();
+        } else if regs.c2.is_set(Control2::TCIE) && regs.s1.is_set(Status1::TC) {
+            self.transmit_done();
+        }
+
         // Read byte from data register; reading S1 and D clears interrupt
         if regs.s1.is_set(Status1::RDRF) {
+            // R8 must be read before D.
+            let ninth = regs.c3.read(Control3::R8);
             let datum: u8 = regs.d.get();
+            self.rx_idle_pending.set(false);
 
-            // Put byte into buffer, trigger callback if buffer full
+            let character = [datum, ninth];
+            let character = &character[..self.options.get().data_bits.width()];
+
+            // Put character into buffer, trigger callback if buffer full. If
+            // no receive is outstanding, hold on to it in the ring buffer.
             let mut done = false;
             let mut index = self.rx_index.get();
-            self.buffer.map( |buf| {
-                buf[index] = datum;
-                index = index + 1;
+            let received = self.buffer.map(|buf| {
+                buf[index..index + character.len()].copy_from_slice(character);
+                index = index + character.len();
                 if index >= self.rx_len.get() {
                     done = true;
                 }
                 self.rx_index.set(index);
             });
+            if received.is_none() {
+                self.ring_push(character);
+            }
             if done {
-                self.client.get().map(|client| {
-                    match self.buffer.take() {
-                        Some(buf) => client.receive_complete(buf, index, uart::Error::CommandComplete),
-                        None => ()
-                    }
-                });
+                self.complete_receive(index);
+            }
+        } else if regs.s1.is_set(Status1::IDLE) {
+            // IDLE is cleared by reading S1 followed by D.
+            regs.d.get();
+
+            let index = self.rx_index.get();
+            if self.buffer.is_some() {
+                if self.rx_idle_complete.get() && index > 0 {
+                    self.complete_receive(index);
+                }
+            } else if self.ring_len.get() > 0 {
+                // The line went idle with no receive outstanding; remember
+                // so the next idle-terminated receive can complete at once.
+                self.rx_idle_pending.set(true);
+            }
+        }
+    }
+
+    /// Refills the transmitter. Once the last byte is queued, waits for it to
+    /// leave the shift register before reporting completion.
+    fn transmit_next(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        let nine_bit = self.options.get().data_bits == DataBits::Nine;
+        let mut index = self.tx_index.get();
+        let len = self.tx_len.get();
+        self.tx_buffer.map(|buf| {
+            // TDRE is cleared by reading S1 followed by writing D. T8 must be
+            // written first.
+            while index < len && regs.s1.is_set(Status1::TRDE) {
+                if nine_bit {
+                    regs.c3.modify(Control3::T8.val(buf[index + 1] & 1));
+                    regs.d.set(buf[index]);
+                    index += 2;
+                } else {
+                    regs.d.set(buf[index]);
+                    index += 1;
+                }
+            }
+        });
+        self.tx_index.set(index);
+
+        if index >= len {
+            regs.c2.modify(Control2::TIE::CLEAR + Control2::TCIE::SET);
+        }
+    }
+
+    fn transmit_done(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.c2.modify(Control2::TCIE::CLEAR);
+
+        self.tx_buffer.take().map(|buf| {
+            self.client.get().map(move |client| {
+                client.transmit_complete(buf, uart::Error::CommandComplete)
+            });
+        });
+    }
+
+    /// True while an interrupt-driven transmission is in progress.
+    pub fn transmit_busy(&self) -> bool {
+        self.tx_buffer.is_some()
+    }
+
+    fn complete_receive(&self, rx_len: usize) {
+        self.rx_complete_pending.set(false);
+        self.rx_idle_complete.set(false);
+        self.disable_idle_interrupt();
+        self.client.get().map(|client| {
+            match self.buffer.take() {
+                Some(buf) => client.receive_complete(buf, rx_len, uart::Error::CommandComplete),
+                None => ()
+            }
+        });
+    }
+
+    fn enable_idle_interrupt(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.c2.modify(Control2::ILIE::SET);
+    }
+
+    fn disable_idle_interrupt(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        if self.ring.is_none() {
+            regs.c2.modify(Control2::ILIE::CLEAR);
+        }
+    }
+
+    /// Rounds a buffer length down to whole characters.
+    fn whole_characters(&self, len: usize) -> usize {
+        let width = self.options.get().data_bits.width();
+        len - len % width
+    }
+
+    /// Provides a buffer in which bytes that arrive while no receive is
+    /// outstanding are kept, so that none are lost between a
+    /// `receive_complete` callback and the next call to `receive`. Buffered
+    /// bytes are delivered first by the next receive. If the ring fills up,
+    /// further bytes are dropped and counted (see `rx_dropped`).
+    pub fn enable_rx_ring(&self, ring: &'static mut [u8]) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        self.ring_head.set(0);
+        self.ring_len.set(0);
+        self.ring.replace(ring);
+
+        // Idle detection is kept on so that an idle line seen while nothing
+        // is outstanding can be remembered for the next receive.
+        regs.c2.modify(Control2::ILIE::SET);
+    }
+
+    /// Stops buffering between receives, returning the ring buffer.
+    pub fn disable_rx_ring(&self) -> Option<&'static mut [u8]> {
+        self.ring_len.set(0);
+        let ring = self.ring.take();
+        if !self.rx_idle_complete.get() {
+            self.disable_idle_interrupt();
+        }
+        ring
+    }
+
+    /// Discards any bytes held in the ring buffer.
+    pub fn clear_rx_ring(&self) {
+        self.ring_len.set(0);
+        self.rx_idle_pending.set(false);
+    }
+
+    /// Discards up to `count` of the oldest bytes in the ring buffer, keeping
+    /// any that arrived after them.
+    pub fn discard_rx(&self, count: usize) {
+        self.ring.map(|ring| {
+            let count = cmp::min(count, self.ring_len.get());
+            self.ring_head.set((self.ring_head.get() + count) % ring.len());
+            self.ring_len.set(self.ring_len.get() - count);
+        });
+    }
+
+    /// Number of bytes discarded because the ring buffer was full.
+    pub fn rx_dropped(&self) -> usize {
+        self.ring_dropped.get()
+    }
+
+    /// Stores a whole character, or drops it if there's no room. Without a
+    /// ring the character is simply discarded.
+    fn ring_push(&self, character: &[u8]) {
+        let head = self.ring_head.get();
+        let len = self.ring_len.get();
+        self.ring.map(|ring| {
+            if len + character.len() <= ring.len() {
+                for (i, byte) in character.iter().enumerate() {
+                    ring[(head + len + i) % ring.len()] = *byte;
+                }
+                self.ring_len.set(len + character.len());
+            } else {
+                self.ring_dropped.set(self.ring_dropped.get() + character.len());
+            }
+        });
+    }
+
+    /// Moves as many buffered bytes as fit into the receive buffer,
+    /// returning the new receive index.
+    fn ring_drain(&self) -> usize {
+        let mut index = self.rx_index.get();
+        let rx_len = self.rx_len.get();
+        self.ring.map(|ring| {
+            self.buffer.map(|buf| {
+                while index < rx_len && self.ring_len.get() > 0 {
+                    let head = self.ring_head.get();
+                    buf[index] = ring[head];
+                    index += 1;
+                    self.ring_head.set((head + 1) % ring.len());
+                    self.ring_len.set(self.ring_len.get() - 1);
+                }
+            });
+        });
+        self.rx_index.set(index);
+        index
+    }
+
+    /// Starts a receive of up to `rx_len` bytes, completing early once the
+    /// line has been idle for one character time after at least one byte.
+    /// The idle period is fixed by the hardware and cannot be lengthened.
+    pub fn receive_until_idle(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.rx_idle_complete.set(true);
+        self.enable_idle_interrupt();
+        self.start_receive(rx_buffer, rx_len);
+    }
+
+    fn start_receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        let mut length = rx_len;
+        if rx_len > rx_buffer.len() {
+            length = rx_buffer.len();
+        }
+        let length = self.whole_characters(length);
+
+        // Bytes may arrive while the buffered ones are copied over.
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.c2.modify(Control2::RIE::CLEAR);
+
+        self.buffer.put(Some(rx_buffer));
+        self.rx_len.set(length);
+        self.rx_index.set(0);
+        let index = self.ring_drain();
+
+        regs.c2.modify(Control2::RIE::SET);
+
+        // The client may be calling from its own receive_complete, so a
+        // receive satisfied by the ring completes from the interrupt path
+        // instead of here.
+        let complete = if index >= length {
+            true
+        } else if index > 0 && self.ring_len.get() == 0 &&
+                  self.rx_idle_complete.get() && self.rx_idle_pending.get() {
+            self.rx_idle_pending.set(false);
+            true
+        } else {
+            false
+        };
+        if complete {
+            self.rx_complete_pending.set(true);
+            unsafe { nvic::set_pending(self.nvic_idx()) };
+        }
+    }
+
+    fn handle_iso7816_interrupt(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        // Only consider the sources we have enabled; the flags are cleared by
+        // writing them back.
+        let status = regs.is7816.get() & regs.ie7816.get();
+        if status == 0 {
+            return;
+        }
+        regs.is7816.set(status);
+
+        let client = match self.iso7816_client.get() {
+            Some(client) => client,
+            None => return,
+        };
+
+        if status & InterruptStatus7816::INITD::SET.value != 0 {
+            regs.ie7816.modify(InterruptEnable7816::INITDE::CLEAR);
+            // The hardware sets MSBF (and inverts the data) when the card
+            // answers with the inverse convention initial character.
+            let inverse = regs.s2.is_set(Status2::MSBF);
+            client.iso7816_event(Iso7816Event::InitialCharacter { inverse: inverse });
+        }
+        if status & InterruptStatus7816::GTV::SET.value != 0 {
+            client.iso7816_event(Iso7816Event::GuardTimeViolation);
+        }
+        if status & InterruptStatus7816::TXT::SET.value != 0 {
+            client.iso7816_event(Iso7816Event::TransmitRetriesExceeded);
+        }
+        if status & InterruptStatus7816::RXT::SET.value != 0 {
+            client.iso7816_event(Iso7816Event::ReceiveRetriesExceeded);
+        }
+        if status & (InterruptStatus7816::WT::SET.value |
+                     InterruptStatus7816::CWT::SET.value |
+                     InterruptStatus7816::BWT::SET.value) != 0 {
+            client.iso7816_event(Iso7816Event::WaitTimeout);
+        }
+    }
+
+    pub fn set_iso7816_client(&self, client: &'static Iso7816Client) {
+        self.iso7816_client.set(Some(client));
+    }
+
+    /// Switches the UART into ISO-7816 (smartcard) mode. The UART must already
+    /// have been initialized with a baud rate of one character per ETU, i.e.
+    /// `card_clock * D / F`; the card clock itself must be provided by the
+    /// board.
+    pub fn enable_iso7816(&self, params: &Iso7816Params) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        regs.c2.modify(Control2::TE::CLEAR + Control2::RE::CLEAR);
+
+        // Characters are 8 data bits plus an even parity bit, one stop bit.
+        regs.c1.modify(Control1::M::NineBit + Control1::PE::SET + Control1::PT::Even);
+        regs.bdh.modify(BaudRateHigh::SBNS::One);
+
+        regs.wn7816.set(params.guard_time);
+        regs.wf7816.set(params.wait_time_multiplier);
+
+        match params.protocol {
+            Iso7816Protocol::T0 => {
+                regs.wp7816.write(WaitParameter7816::WI.val(params.wait_time_integer));
+                regs.et7816.write(ErrorThreshold7816::TXTHRESHOLD.val(params.retries) +
+                                  ErrorThreshold7816::RXTHRESHOLD.val(params.retries));
+                // T=0 signals parity errors by NACKing the character, which
+                // the sender then retransmits.
+                regs.c7816.write(Control7816::ISO_7816E::SET +
+                                 Control7816::TTYPE::T0 +
+                                 Control7816::ANACK::SET +
+                                 Control7816::ONACK::SET);
+                regs.ie7816.write(InterruptEnable7816::WTE::SET +
+                                  InterruptEnable7816::GTVE::SET +
+                                  InterruptEnable7816::TXTE::SET +
+                                  InterruptEnable7816::RXTE::SET);
+            }
+            Iso7816Protocol::T1 => {
+                regs.wp7816.write(WaitParameter7816::CWI.val(params.cwi) +
+                                  WaitParameter7816::BWI.val(params.bwi));
+                regs.c7816.write(Control7816::ISO_7816E::SET +
+                                 Control7816::TTYPE::T1);
+                regs.ie7816.write(InterruptEnable7816::CWTE::SET +
+                                  InterruptEnable7816::BWTE::SET +
+                                  InterruptEnable7816::GTVE::SET);
             }
         }
+
+        regs.c2.modify(Control2::TE::SET + Control2::RE::SET);
+    }
+
+    pub fn disable_iso7816(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.ie7816.set(0);
+        regs.c7816.set(0);
+        regs.is7816.set(0xFF);
+    }
+
+    /// Sets the extra guard time N, in ETUs, inserted between characters sent
+    /// to the card.
+    pub fn set_iso7816_guard_time(&self, guard_time: u8) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.wn7816.set(guard_time);
+    }
+
+    /// Arms detection of the initial character (TS) of an answer to reset.
+    /// Once it is seen, the data convention is configured automatically and
+    /// an `InitialCharacter` event is delivered.
+    pub fn detect_initial_character(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        regs.is7816.write(InterruptStatus7816::INITD::SET);
+        regs.c7816.modify(Control7816::INIT::SET);
+        regs.ie7816.modify(InterruptEnable7816::INITDE::SET);
     }
 
     pub fn handle_error(&self) {
@@ -70,6 +691,7 @@ impl Uart {
 
     fn set_parity(&self, parity: hil::uart::Parity) {
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        self.parity.set(parity);
 
         let (pe, pt) = match parity {
             hil::uart::Parity::None => (Control1::PE::CLEAR, Control1::PT::Even),
@@ -77,15 +699,117 @@ impl Uart {
             hil::uart::Parity::Odd => (Control1::PE::SET, Control1::PT::Odd)
         };
 
+        // The parity bit, when enabled, takes the place of the most
+        // significant data bit, so frames with a parity bit need one more bit.
+        let parity_bits = match parity {
+            hil::uart::Parity::None => 0,
+            _ => 1
+        };
+        let data_bits = match self.options.get().data_bits {
+            DataBits::Eight => 8,
+            DataBits::Nine => 9
+        };
+        let (m, m10) = match data_bits + parity_bits {
+            8 => (Control1::M::EightBit, Control4::M10::CLEAR),
+            9 => (Control1::M::NineBit, Control4::M10::CLEAR),
+            _ => (Control1::M::NineBit, Control4::M10::SET)
+        };
+
+        // Address mark wakeup relies on the ninth bit; `valid` rules out
+        // address matching with eight data bits.
+        let wake = if self.options.get().address_match.enabled() {
+            Control1::WAKE::AddressMark
+        } else {
+            Control1::WAKE::Idle
+        };
+
         // This basic procedure outlined in section 59.9.3.
         // Set control register 1, which configures the parity.
         regs.c1.write(pe + pt +
                       Control1::LOOPS::CLEAR +
                       Control1::UARTSWAI::CLEAR +
                       Control1::RSRC::CLEAR +
-                      Control1::M::EightBit +
-                      Control1::WAKE::Idle +
+                      m +
+                      wake +
                       Control1::ILT::AfterStop);
+        regs.c4.modify(m10);
+    }
+
+    /// Changes the frame options of a configured UART. The options are kept
+    /// across calls to `init`. Switching between eight and nine data bits
+    /// while a transfer is outstanding garbles it. Returns EINVAL for
+    /// address matching without nine data bits.
+    pub fn set_options(&self, options: UartOptions) -> ReturnCode {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        if !options.valid() {
+            return ReturnCode::EINVAL;
+        }
+        self.options.set(options);
+
+        // The transmitter and receiver must be disabled while the frame
+        // format changes.
+        let te = regs.c2.read(Control2::TE);
+        let re = regs.c2.read(Control2::RE);
+        regs.c2.modify(Control2::TE::CLEAR + Control2::RE::CLEAR);
+
+        self.set_parity(self.parity.get());
+        self.set_irda(options.irda);
+        self.set_address_match(options.address_match);
+        self.set_rs485(options.rs485);
+
+        regs.c2.modify(Control2::TE.val(te) + Control2::RE.val(re));
+        ReturnCode::SUCCESS
+    }
+
+    fn set_irda(&self, irda: Option<IrdaPulseWidth>) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        match irda {
+            None => regs.ir.write(Infrared::IREN::CLEAR),
+            Some(width) => {
+                let tnp = match width {
+                    IrdaPulseWidth::ThreeSixteenths => Infrared::TNP::ThreeSixteenths,
+                    IrdaPulseWidth::OneSixteenth => Infrared::TNP::OneSixteenth,
+                    IrdaPulseWidth::OneThirtySecond => Infrared::TNP::OneThirtySecond,
+                    IrdaPulseWidth::OneQuarter => Infrared::TNP::OneQuarter
+                };
+                regs.ir.write(Infrared::IREN::SET + tnp);
+            }
+        }
+    }
+
+    fn set_address_match(&self, addresses: AddressMatch) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        regs.ma1.set(addresses.ma1.unwrap_or(0));
+        regs.ma2.set(addresses.ma2.unwrap_or(0));
+        regs.c4.modify(Control4::MAEN1.val(addresses.ma1.is_some() as u8) +
+                       Control4::MAEN2.val(addresses.ma2.is_some() as u8));
+    }
+
+    fn set_rs485(&self, rs485: Option<TransmitEnablePolarity>) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        match rs485 {
+            None => regs.modem.write(Modem::TXRTSE::CLEAR),
+            Some(polarity) => {
+                let pol = match polarity {
+                    TransmitEnablePolarity::ActiveLow => Modem::TXRTSPOL::ActiveLow,
+                    TransmitEnablePolarity::ActiveHigh => Modem::TXRTSPOL::ActiveHigh
+                };
+                regs.modem.write(Modem::TXRTSE::SET + pol);
+            }
+        }
+    }
+
+    /// Puts the receiver to sleep until an address character matching MA1 or
+    /// MA2 arrives. Characters for other nodes are discarded by hardware;
+    /// call this again once the message addressed to this node is done.
+    pub fn sleep_until_address(&self) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        if self.options.get().address_match.enabled() {
+            regs.c2.modify(Control2::RWU::SET);
+        }
     }
 
     fn set_stop_bits(&self, stop_bits: hil::uart::StopBits) {
@@ -99,22 +823,46 @@ impl Uart {
         regs.bdh.modify(stop_bits);
     }
 
-    fn set_baud_rate(&self, baud_rate: u32) {
-        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
-
-        // Baud rate generation. Note that UART0 and UART1 are sourced from the core clock, not the
-        // bus clock.
-        let uart_clock = match self.index {
+    fn clock_hz(&self) -> u32 {
+        // UART0 and UART1 are sourced from the core clock, not the bus clock.
+        match self.index {
             0 | 1 => clock::core_clock_hz(),
             _ => clock::peripheral_clock_hz()
-        };
+        }
+    }
 
-        let baud_counter: u32 = (uart_clock >> 4) / baud_rate;
+    /// Configures the baud rate generator, returning the baud rate actually
+    /// achieved. The registers are left untouched if the requested rate cannot
+    /// be generated within `BAUD_RATE_TOLERANCE` of the current UART clock.
+    pub fn set_baud_rate(&self, baud_rate: u32) -> Result<u32, ReturnCode> {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        let divisor = baud_divisor(self.clock_hz(), baud_rate)?;
+
+        // The new SBR value only takes effect once BDL is written, so BDH must
+        // be written first.
+        regs.c4.modify(Control4::BRFA.val(divisor.brfa));
+        regs.bdh.modify(BaudRateHigh::SBR.val((divisor.sbr >> 8) as u8));
+        regs.bdl.set(divisor.sbr as u8);
+
+        self.requested_baud_rate.set(baud_rate);
+        Ok(divisor.baud_rate)
+    }
 
-        // Set the baud rate.
-        regs.c4.modify(Control4::BRFA.val(0));
-        regs.bdh.modify(BaudRateHigh::SBR.val((baud_counter >> 8) as u8));
-        regs.bdl.set(baud_counter as u8);
+    /// True while the UART is stopped because the current clock can't
+    /// generate its baud rate.
+    pub fn paused(&self) -> bool {
+        self.paused.get().is_some()
+    }
+
+    /// Returns the baud rate currently generated by this UART.
+    pub fn get_baud_rate(&self) -> u32 {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        let sbr = ((regs.bdh.read(BaudRateHigh::SBR) as u16) << 8) | regs.bdl.get() as u16;
+        let brfa = regs.c4.read(Control4::BRFA);
+
+        baud_rate(self.clock_hz(), sbr, brfa)
     }
 
     pub fn enable_rx(&self) {
@@ -128,15 +876,19 @@ impl Uart {
         regs.rwfifo.set(1);               // Issue interrupt on each byte
         regs.c5.modify(Control5::RDMAS::CLEAR); // Issue interrupt on RX data
 
+        unsafe { nvic::enable(self.nvic_idx()) };
+        regs.c2.modify(Control2::RIE::SET);     // Enable interrupts
+    }
+
+    fn nvic_idx(&self) -> nvic::NvicIdx {
         match self.index {
-            0 => unsafe {nvic::enable(nvic::NvicIdx::UART0)},
-            1 => unsafe {nvic::enable(nvic::NvicIdx::UART1)},
-            2 => unsafe {nvic::enable(nvic::NvicIdx::UART2)},
-            3 => unsafe {nvic::enable(nvic::NvicIdx::UART3)},
-            4 => unsafe {nvic::enable(nvic::NvicIdx::UART4)},
+            0 => nvic::NvicIdx::UART0,
+            1 => nvic::NvicIdx::UART1,
+            2 => nvic::NvicIdx::UART2,
+            3 => nvic::NvicIdx::UART3,
+            4 => nvic::NvicIdx::UART4,
             _ => unreachable!()
-        };
-        regs.c2.modify(Control2::RIE::SET);     // Enable interrupts
+        }
     }
 
     pub fn enable_tx(&self) {
@@ -156,6 +908,26 @@ impl Uart {
         };
     }
 
+    /// Transmits from the TDRE interrupt, following the procedure outlined in
+    /// section 59.9.3. The client is called once the last stop bit has been
+    /// sent, which half-duplex users rely on to turn the line around. Fails
+    /// with `EBUSY`, returning the buffer, while a transmission is in
+    /// progress.
+    pub fn transmit_buffer(&self, tx_data: &'static mut [u8], tx_len: usize)
+                           -> (ReturnCode, Option<&'static mut [u8]>) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        if self.tx_buffer.is_some() {
+            return (ReturnCode::EBUSY, Some(tx_data));
+        }
+
+        self.tx_len.set(self.whole_characters(::core::cmp::min(tx_len, tx_data.len())));
+        self.tx_index.set(0);
+        self.tx_buffer.replace(tx_data);
+        regs.c2.modify(Control2::TIE::SET);
+        (ReturnCode::SUCCESS, None)
+    }
+
     pub fn send_byte(&self, byte: u8) {
         let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
 
@@ -176,44 +948,206 @@ impl hil::uart::UART for Uart {
         self.client.set(Some(client));
     }
 
+    /// Configures and enables the UART with the current options. Panics if
+    /// the baud rate can't be generated from the UART's clock; use
+    /// `UartExtended::configure` to handle that instead.
     fn init(&self, params: uart::UARTParams) {
+        let baud_rate = params.baud_rate;
+        if let Err(err) = UartExtended::configure(self, params, self.options.get()) {
+            panic!("UART{} can't run at {} baud: {:?}", self.index, baud_rate, err);
+        }
+    }
+
+    /// See `transmit_buffer`. If a transmission is already in progress, the
+    /// buffer is handed back from the interrupt path with `RepeatCallError`.
+    /// Only one such buffer is held; if one is already waiting to go back,
+    /// the new one is handed back at once, from within this call.
+    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
+        let (result, rejected) = self.transmit_buffer(tx_data, tx_len);
+        if result != ReturnCode::SUCCESS {
+            rejected.map(|buf| {
+                if self.tx_rejected.is_some() {
+                    self.client.get().map(move |client| {
+                        client.transmit_complete(buf, uart::Error::RepeatCallError)
+                    });
+                } else {
+                    self.tx_rejected.replace(buf);
+                    unsafe { nvic::set_pending(self.nvic_idx()) };
+                }
+            });
+        }
+    }
+
+    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
+        self.rx_idle_complete.set(false);
+        self.disable_idle_interrupt();
+        self.start_receive(rx_buffer, rx_len);
+    }
+
+    fn abort_receive(&self) {
+        if self.buffer.is_some() {
+            let index = self.rx_index.get();
+            self.complete_receive(index);
+        }
+    }
+}
+
+impl UartExtended for Uart {
+    fn configure(&self, params: uart::UARTParams, options: UartOptions) -> Result<u32, ReturnCode> {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+        if !options.valid() {
+            return Err(ReturnCode::EINVAL);
+        }
         self.enable_clock();
 
+        // The transmitter and receiver must be disabled while the frame
+        // format changes.
+        regs.c2.modify(Control2::TE::CLEAR + Control2::RE::CLEAR);
+        self.paused.set(None);
+
+        self.options.set(options);
         self.set_parity(params.parity);
+        self.set_irda(options.irda);
+        self.set_address_match(options.address_match);
+        self.set_rs485(options.rs485);
         self.set_stop_bits(params.stop_bits);
-        self.set_baud_rate(params.baud_rate);
+        let baud_rate = self.set_baud_rate(params.baud_rate)?;
 
         self.enable_rx();
         self.enable_rx_interrupts();
         self.enable_tx();
+        Ok(baud_rate)
     }
 
-    fn transmit(&self, tx_data: &'static mut [u8], tx_len: usize) {
-        // This basic procedure outlined in section 59.9.3.
-        for i in 0..tx_len {
-            self.send_byte(tx_data[i]);
-        }
+    fn options(&self) -> UartOptions {
+        self.options.get()
+    }
+}
 
-        while !self.tx_ready() {}
+/// Implementation of kernel::hil::UARTAdvanced
+impl hil::uart::UARTAdvanced for Uart {
+    /// Receives until the buffer is full or the line goes idle. The K66 only
+    /// detects a fixed idle period of one character, so `interbyte_timeout`
+    /// is ignored.
+    fn receive_automatic(&self, rx_buffer: &'static mut [u8], _interbyte_timeout: u8) {
+        let len = rx_buffer.len();
+        self.receive_until_idle(rx_buffer, len);
+    }
+}
+
+impl smc::SleepVeto for Uart {
+    /// The UART is clocked by the system or bus clock, which stop in stop
+    /// modes, so it keeps the chip in WAIT while sending or while a
+    /// character is coming in. A receiver that is only waiting allows a
+    /// stop mode: this arms the RX edge interrupt, and the start bit of the
+    /// next character wakes the chip. That character arrives while the
+    /// clocks restart, so it is usually lost or garbled.
+    fn deepest_sleep(&self) -> smc::SleepMode {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
 
-        self.client.get().map(move |client|
-            client.transmit_complete(tx_data, uart::Error::CommandComplete)
-        );
+        let receiving = || {
+            regs.s2.is_set(Status2::RAF) || regs.s1.is_set(Status1::RDRF) ||
+            self.rx_complete_pending.get()
+        };
+        if self.transmit_busy() || self.tx_rejected.is_some() || receiving() {
+            return smc::SleepMode::Wait;
+        }
+
+        if self.buffer.is_some() || self.ring.is_some() {
+            regs.s2.modify(Status2::RXEDGIF::SET);
+            regs.bdh.modify(BaudRateHigh::RXEDGIE::SET);
+            // A start bit seen before the edge interrupt was armed wouldn't
+            // wake the chip.
+            if receiving() {
+                return smc::SleepMode::Wait;
+            }
+        }
+        smc::SleepMode::VeryLowPowerStop
     }
+}
 
-    #[allow(unused_variables)]
-    fn receive(&self, rx_buffer: &'static mut [u8], rx_len: usize) {
-        let mut length = rx_len;
-        if rx_len > rx_buffer.len() {
-            length = rx_buffer.len();
+impl clock::ClockClient for Uart {
+    /// Regenerates the requested baud rate from the new UART clock. If it
+    /// can no longer be generated, the transmitter and receiver are stopped
+    /// rather than run at the wrong rate, and outstanding transfers wait
+    /// until a later clock change makes the rate reachable again.
+    fn clocks_changed(&self, _previous: clock::Clocks) {
+        let regs: &mut Registers = unsafe { mem::transmute(self.registers) };
+
+        let baud_rate = self.requested_baud_rate.get();
+        if baud_rate == 0 {
+            return;
         }
 
-        self.buffer.put(Some(rx_buffer));
-        self.rx_len.set(length);
-        self.rx_index.set(0);
+        match self.set_baud_rate(baud_rate) {
+            Ok(_) => {
+                self.paused.take().map(|(te, re)| {
+                    regs.c2.modify(Control2::TE.val(te) + Control2::RE.val(re));
+                });
+            }
+            Err(_) => {
+                if self.paused.get().is_none() {
+                    self.paused.set(Some((regs.c2.read(Control2::TE), regs.c2.read(Control2::RE))));
+                    regs.c2.modify(Control2::TE::CLEAR + Control2::RE::CLEAR);
+                }
+            }
+        }
     }
+}
 
-    fn abort_receive(&self) {
-        unimplemented!();
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn divisor_for_common_rates() {
+        // 120 MHz: 120e6 / (16 * 115200) = 65.10, i.e. SBR 65 and 3/32.
+        assert_eq!(baud_divisor(120_000_000, 115200),
+                   Ok(BaudDivisor { sbr: 65, brfa: 3, baud_rate: 115218 }));
+        // 60 MHz: 60e6 / (16 * 9600) = 390.625 exactly, SBR 390 and 20/32.
+        assert_eq!(baud_divisor(60_000_000, 9600),
+                   Ok(BaudDivisor { sbr: 390, brfa: 20, baud_rate: 9600 }));
+    }
+
+    #[test]
+    fn baud_rate_from_divisor() {
+        assert_eq!(baud_rate(120_000_000, 65, 3), 115218);
+        assert_eq!(baud_rate(60_000_000, 390, 20), 9600);
+        // BRFA only has five bits.
+        assert_eq!(baud_rate(60_000_000, 390, 20 | 0x20), 9600);
+        assert_eq!(baud_rate(60_000_000, 0, 0), 0);
+    }
+
+    #[test]
+    fn tolerance_edge() {
+        // With a 1 MHz clock, 61539 to 63492 baud all round to SBR 1, BRFA 0,
+        // which gives 62500 baud. Only the middle of that range is within
+        // 1.5%.
+        let divisor = BaudDivisor { sbr: 1, brfa: 0, baud_rate: 62500 };
+        assert_eq!(baud_divisor(1_000_000, 61577), Ok(divisor));
+        assert_eq!(baud_divisor(1_000_000, 61576), Err(ReturnCode::EINVAL));
+        assert_eq!(baud_divisor(1_000_000, 63451), Ok(divisor));
+        assert_eq!(baud_divisor(1_000_000, 63452), Err(ReturnCode::EINVAL));
+    }
+
+    #[test]
+    fn divisor_out_of_range() {
+        assert_eq!(baud_divisor(120_000_000, 0), Err(ReturnCode::EINVAL));
+        // The divisor rounds to 31/32, so SBR would be 0.
+        assert_eq!(baud_divisor(1_000_000, 63493), Err(ReturnCode::EINVAL));
+        // 180e6 / (16 * 1200) = 9375, which needs more than 13 bits of SBR.
+        assert_eq!(baud_divisor(180_000_000, 1200), Err(ReturnCode::EINVAL));
+        // 0x1FFF, the largest SBR, still works.
+        assert_eq!(baud_divisor(16 * 0x1FFF, 1).map(|d| d.sbr), Ok(0x1FFF));
+    }
+
+    #[test]
+    fn address_match_needs_nine_bits() {
+        let mut options = UartOptions::default();
+        assert!(options.valid());
+        options.address_match.ma1 = Some(0x12);
+        assert!(!options.valid());
+        options.data_bits = DataBits::Nine;
+        assert!(options.valid());
     }
 }
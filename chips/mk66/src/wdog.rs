@@ -1,9 +1,43 @@
 //! Implementation of the MK66 hardware watchdog timer
+//!
+//! Once started, the watchdog resets the chip unless it is refreshed within
+//! the timeout. `service` is called from the kernel loop, so a kernel that
+//! stops looping resets itself. The watchdog's windowed mode is not used:
+//! the kernel loop refreshes it on every pass, however soon after the last
+//! one, so a window would reset a kernel that is merely busy. The watchdog
+//! pauses while the core sleeps, so a kernel that is idle rather than stuck
+//! is never reset.
 
+use core::cmp;
 use core::mem;
+use support;
 use kernel::hil;
+use clock;
 use regs::wdog::*;
 
+/// The LPO clock is a 1 kHz oscillator that keeps running when the system
+/// clocks fail.
+const LPO_HZ: u32 = 1000;
+
+/// The counter must be given at least this many ticks.
+const MIN_TIMEOUT_TICKS: u32 = 4;
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+pub enum ClockSource {
+    /// The 1 kHz low-power oscillator.
+    Lpo,
+    /// The bus clock. Finer grained, but it stops if the clocks fail, and
+    /// the timeout changes with the bus frequency.
+    Bus,
+}
+
+#[derive(Copy, Clone)]
+pub struct Config {
+    pub clock: ClockSource,
+    /// Milliseconds without a refresh before the chip is reset.
+    pub timeout_ms: u32,
+}
+
 #[inline]
 fn unlock() {
     let regs: &mut Registers = unsafe { mem::transmute(WDOG) };
@@ -11,14 +45,71 @@ fn unlock() {
     regs.unlock.write(Unlock::KEY::Key1);
     regs.unlock.write(Unlock::KEY::Key2);
     unsafe {
-        asm!("nop" :::: "volatile");
-        asm!("nop" :::: "volatile");
+        support::nop();
+        support::nop();
     }
 }
 
-#[allow(unused_variables)]
+/// Converts milliseconds to prescaled watchdog ticks.
+fn ms_to_ticks(ms: u32, source_hz: u32, prescaler: u32) -> u32 {
+    let ticks = (source_hz as u64 * ms as u64) / (1000 * prescaler as u64);
+    cmp::min(ticks, u32::max_value() as u64) as u32
+}
+
+/// Starts the watchdog on the LPO clock with a timeout of `period`
+/// milliseconds.
 pub fn start(period: usize) {
-    unimplemented!();
+    configure(Config {
+        clock: ClockSource::Lpo,
+        timeout_ms: cmp::min(period, u32::max_value() as usize) as u32,
+    });
+}
+
+/// Starts the watchdog, or reconfigures it if it is already running.
+pub fn configure(config: Config) {
+    let regs: &mut Registers = unsafe { mem::transmute(WDOG) };
+
+    let source_hz = match config.clock {
+        ClockSource::Lpo => LPO_HZ,
+        ClockSource::Bus => clock::bus_clock_hz(),
+    };
+
+    // Use the smallest prescaler (1 to 8) that fits the timeout in the
+    // 32-bit counter, for the finest resolution.
+    let total = source_hz as u64 * config.timeout_ms as u64 / 1000;
+    let prescaler = (1..9).find(|p| total / p <= u32::max_value() as u64).unwrap_or(8) as u32;
+
+    let timeout = cmp::max(ms_to_ticks(config.timeout_ms, source_hz, prescaler), MIN_TIMEOUT_TICKS);
+
+    let clksrc = match config.clock {
+        ClockSource::Lpo => StatusAndControlHigh::CLKSRC::CLEAR,
+        ClockSource::Bus => StatusAndControlHigh::CLKSRC::SET,
+    };
+
+    // The new configuration has to be written within 256 bus clocks of
+    // unlocking, so nothing may interrupt it.
+    unsafe {
+        support::atomic(|| {
+            unlock();
+
+            regs.tovalh.set((timeout >> 16) as u16);
+            regs.tovall.set(timeout as u16);
+            regs.presc.write(Prescaler::PRESCVAL.val(prescaler as u16 - 1));
+
+            // Stop counting while the core sleeps, in wait or stop modes, or
+            // is halted by a debugger: an idle kernel may sleep for longer
+            // than the timeout. Updates stay allowed so the watchdog can be
+            // stopped or reconfigured.
+            regs.stctrlh.write(StatusAndControlHigh::ALLOWUPDATE::SET +
+                               StatusAndControlHigh::WAITEN::CLEAR +
+                               StatusAndControlHigh::STOPEN::CLEAR +
+                               StatusAndControlHigh::DBGEN::CLEAR +
+                               StatusAndControlHigh::IRQSTEN::CLEAR +
+                               clksrc +
+                               StatusAndControlHigh::WINEN::CLEAR +
+                               StatusAndControlHigh::WDOGEN::SET);
+        });
+    }
 }
 
 pub fn stop() {
@@ -39,8 +130,26 @@ pub fn stop() {
 
 pub fn tickle() {
     let regs: &mut Registers = unsafe { mem::transmute(WDOG) };
-    regs.refresh.write(Refresh::KEY::Key1);
-    regs.refresh.write(Refresh::KEY::Key2);
+
+    // The two writes must be no more than 20 bus clocks apart.
+    unsafe {
+        support::atomic(|| {
+            regs.refresh.write(Refresh::KEY::Key1);
+            regs.refresh.write(Refresh::KEY::Key2);
+        });
+    }
+}
+
+pub fn enabled() -> bool {
+    let regs: &mut Registers = unsafe { mem::transmute(WDOG) };
+    regs.stctrlh.is_set(StatusAndControlHigh::WDOGEN)
+}
+
+/// Refreshes the watchdog if it is running.
+pub fn service() {
+    if enabled() {
+        tickle();
+    }
 }
 
 pub struct Wdog;